@@ -0,0 +1,187 @@
+// benches/deadlines.rs
+
+//! Benchmarks of the deadlines of the timer subsystem with 1M pending timers,
+//! compared to spawning a tokio task per timer.
+//!
+//! Run with `cargo bench --bench deadlines`.
+
+#[allow(dead_code)]
+#[path = "../src/deadlines.rs"]
+mod deadlines;
+
+use chrono::prelude::*;
+use tokio::time;
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+use std::time::Instant;
+
+use deadlines::Deadlines;
+
+const TIMERS: usize = 1_000_000;
+
+fn main() {
+    let now = Local::now();
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+
+    // Deadlines spread out over a day, in whole milliseconds.
+    let expirations: Vec<DateTime<Local>> = (0..TIMERS)
+        .map(|_| now + chrono::Duration::milliseconds((random.next() % 86_400_000) as i64))
+        .collect();
+
+    println!("{} pending timers", TIMERS);
+
+    bench_deadlines(now, &expirations);
+    bench_tokio_tasks(now, &expirations);
+}
+
+fn bench_deadlines(now: DateTime<Local>, expirations: &[DateTime<Local>]) {
+    let mut deadlines = Deadlines::default();
+
+    measure("deadlines: insert", TIMERS, || {
+        for (id, expires_at) in expirations.iter().enumerate() {
+            deadlines.insert((0u32, id as i64), *expires_at);
+        }
+    });
+
+    measure("deadlines: next", TIMERS, || {
+        for _ in 0..TIMERS {
+            std::hint::black_box(deadlines.next());
+        }
+    });
+
+    measure("deadlines: reschedule", TIMERS, || {
+        for (id, expires_at) in expirations.iter().enumerate() {
+            deadlines.insert((0u32, id as i64), *expires_at + chrono::Duration::seconds(1));
+        }
+    });
+
+    measure("deadlines: cancel", TIMERS / 2, || {
+        for id in (0..TIMERS).step_by(2) {
+            deadlines.remove(&(0u32, id as i64));
+        }
+    });
+
+    let mut expired = 0;
+
+    measure("deadlines: expire", TIMERS / 2, || {
+        while deadlines.pop_due(now + chrono::Duration::days(2)).is_some() {
+            expired += 1;
+        }
+    });
+
+    assert_eq!(expired, TIMERS / 2);
+}
+
+fn bench_tokio_tasks(now: DateTime<Local>, expirations: &[DateTime<Local>]) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+
+    // The tasks only register their sleeps once they are polled, so the
+    // measurements include running them up to that point.
+    let polled = Arc::new(AtomicUsize::new(0));
+
+    // Aborting a task only schedules it, the runtime drops it later. The
+    // measurements include running the aborted tasks until they are dropped.
+    let dropped = Arc::new(AtomicUsize::new(0));
+
+    let spawn = |duration: StdDuration| {
+        let polled = polled.clone();
+        let guard = DropCounter(dropped.clone());
+
+        tokio::spawn(async move {
+            let _guard = guard;
+
+            polled.fetch_add(1, Ordering::Relaxed);
+            time::sleep(duration).await;
+        })
+        .abort_handle()
+    };
+
+    runtime.block_on(async {
+        let mut handles = Vec::with_capacity(TIMERS);
+
+        let start = Instant::now();
+
+        for expires_at in expirations {
+            handles.push(spawn((*expires_at - now).to_std().unwrap_or(StdDuration::ZERO)));
+        }
+
+        while polled.load(Ordering::Relaxed) < TIMERS {
+            tokio::task::yield_now().await;
+        }
+
+        report("tokio tasks: insert", TIMERS, start.elapsed());
+
+        let start = Instant::now();
+
+        for (handle, expires_at) in handles.iter_mut().zip(expirations) {
+            let duration = (*expires_at - now).to_std().unwrap_or(StdDuration::ZERO);
+
+            handle.abort();
+            *handle = spawn(duration + StdDuration::from_secs(1));
+        }
+
+        while polled.load(Ordering::Relaxed) < 2 * TIMERS
+            || dropped.load(Ordering::Relaxed) < TIMERS
+        {
+            tokio::task::yield_now().await;
+        }
+
+        report("tokio tasks: reschedule", TIMERS, start.elapsed());
+
+        measure("tokio tasks: cancel", TIMERS / 2, || {
+            for handle in handles.iter().step_by(2) {
+                handle.abort();
+            }
+        });
+
+        let start = Instant::now();
+
+        while dropped.load(Ordering::Relaxed) < TIMERS + TIMERS / 2 {
+            tokio::task::yield_now().await;
+        }
+
+        report("tokio tasks: drop", TIMERS / 2, start.elapsed());
+    });
+}
+
+/// Counts the tasks that have been dropped, after completing or being aborted.
+struct DropCounter(Arc<AtomicUsize>);
+
+impl Drop for DropCounter {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn measure<F: FnOnce()>(name: &str, timers: usize, f: F) {
+    let start = Instant::now();
+    f();
+    report(name, timers, start.elapsed());
+}
+
+fn report(name: &str, timers: usize, elapsed: StdDuration) {
+    println!(
+        "{:<24} {:>10.1} ms {:>10.1} ns/timer",
+        name,
+        elapsed.as_secs_f64() * 1e3,
+        elapsed.as_secs_f64() * 1e9 / timers as f64
+    );
+}
+
+/// A xorshift generator, which is good enough for spreading out deadlines.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
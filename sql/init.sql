@@ -3,7 +3,22 @@
 ---
 
 create table quartz.timer_relations (
-    relid oid primary key
+    relid oid primary key,
+    callback regprocedure,
+    max_attempts integer not null default 1 check (max_attempts >= 1),
+    retry_backoff interval not null default '1 second',
+    retry_backoff_max interval not null default '1 hour',
+    retry_jitter double precision not null default 0.1 check (retry_jitter between 0 and 1),
+    notify_channel text,
+    redeliver boolean not null default false,
+    misfire_policy text not null default 'fire_all'
+        check (misfire_policy in ('fire_all', 'fire_latest', 'skip', 'catch_up')),
+    misfire_threshold interval not null default '1 minute' check (misfire_threshold >= interval '0'),
+    misfire_key text check (misfire_key is null or misfire_policy = 'fire_latest'),
+    misfire_rate double precision check (misfire_rate > 0),
+    column_mapping jsonb not null,
+    enabled boolean not null default true,
+    check ((misfire_policy = 'catch_up') = (misfire_rate is not null))
 );
 
 create function quartz.check_relation_is_table()
@@ -26,3 +41,106 @@ $$ language plpgsql;
 create trigger check_relation_is_table
     before insert or update on quartz.timer_relations
     for each row execute function quartz.check_relation_is_table();
+
+create table quartz.spilled_events (
+    id bigserial primary key,
+    relid oid not null,
+    timer_id bigint
+);
+
+-- Event triggers run as whoever issues the DDL, who need not have any
+-- privileges on quartz.timer_relations, so their functions are security
+-- definers.
+create function quartz.on_sql_drop()
+returns event_trigger
+as $$
+declare
+    dropped record;
+begin
+    -- The extension itself is being dropped.
+    if to_regclass('quartz.timer_relations') is null then
+        return;
+    end if;
+
+    for dropped in
+        delete from quartz.timer_relations tr
+        using pg_event_trigger_dropped_objects() o
+        where o.object_type = 'table' and o.objid = tr.relid
+        returning tr.relid, tr.enabled
+    loop
+        -- Deactivated tables are not tracked.
+        if dropped.enabled then
+            perform quartz.timers_table_dropped(dropped.relid);
+        end if;
+    end loop;
+end;
+$$ language plpgsql security definer set search_path = pg_catalog, pg_temp;
+
+create event trigger quartz_sql_drop
+    on sql_drop
+    execute function quartz.on_sql_drop();
+
+create function quartz.on_ddl_command_end()
+returns event_trigger
+as $$
+declare
+    altered record;
+begin
+    for altered in
+        select distinct c.objid
+        from pg_event_trigger_ddl_commands() c
+        join quartz.timer_relations tr on tr.relid = c.objid
+        where c.object_type = 'table'
+    loop
+        perform quartz.check_timers_table(altered.objid);
+    end loop;
+end;
+$$ language plpgsql security definer set search_path = pg_catalog, pg_temp;
+
+create event trigger quartz_ddl_command_end
+    on ddl_command_end
+    when tag in ('ALTER TABLE')
+    execute function quartz.on_ddl_command_end();
+
+create type quartz.timer_id as (
+    relid regclass,
+    id bigint
+);
+
+comment on type quartz.timer_id is
+    'Identifies a timer across all timers tables, by its table and its ID in that table.';
+
+create function quartz.complete_timer(timer quartz.timer_id)
+returns void
+as $$
+begin
+    if timer.relid is null or timer.id is null then
+        raise exception 'quartz.complete_timer(): timer % is incomplete', timer;
+    end if;
+
+    perform quartz.complete_timer(timer.relid::text, timer.id);
+end;
+$$ language plpgsql strict;
+
+create function quartz.complete_timers(timers quartz.timer_id[])
+returns void
+as $$
+declare
+    completed record;
+begin
+    if exists (select 1 from unnest(timers) t where t.relid is null or t.id is null) then
+        raise exception 'quartz.complete_timers(): timers must not be incomplete';
+    end if;
+
+    -- Tables are completed in a fixed order, so that concurrent calls lock
+    -- their timers in the same order.
+    for completed in
+        select t.relid, array_agg(t.id order by t.id) as ids
+        from unnest(timers) t
+        group by t.relid
+        order by t.relid
+    loop
+        perform quartz.complete_timers(completed.relid::text, completed.ids);
+    end loop;
+end;
+$$ language plpgsql strict;
@@ -4,52 +4,52 @@ This file is auto generated by pgrx.
 The ordering of items is not stable, it is driven by a dependency graph.
 */
 
--- src/lib.rs:31
+-- src/lib.rs:41
 CREATE SCHEMA IF NOT EXISTS quartz; /* quartz::quartz */
 
--- src/lib.rs:47
+-- src/lib.rs:57
 -- quartz::quartz::quartz_timers_before_update
 CREATE FUNCTION quartz."quartz_timers_before_update"()
 	RETURNS TRIGGER
 	LANGUAGE c
 	AS 'MODULE_PATHNAME', 'quartz_timers_before_update_wrapper';
 
--- src/lib.rs:47
+-- src/lib.rs:57
 -- quartz::quartz::quartz_timers_before_insert
 CREATE FUNCTION quartz."quartz_timers_before_insert"()
 	RETURNS TRIGGER
 	LANGUAGE c
 	AS 'MODULE_PATHNAME', 'quartz_timers_before_insert_wrapper';
 
--- src/lib.rs:47
+-- src/lib.rs:57
 -- quartz::quartz::quartz_timers_before_delete
 CREATE FUNCTION quartz."quartz_timers_before_delete"()
 	RETURNS TRIGGER
 	LANGUAGE c
 	AS 'MODULE_PATHNAME', 'quartz_timers_before_delete_wrapper';
 
--- src/lib.rs:47
+-- src/lib.rs:57
 -- quartz::quartz::quartz_timers_after_update
 CREATE FUNCTION quartz."quartz_timers_after_update"()
 	RETURNS TRIGGER
 	LANGUAGE c
 	AS 'MODULE_PATHNAME', 'quartz_timers_after_update_wrapper';
 
--- src/lib.rs:47
+-- src/lib.rs:57
 -- quartz::quartz::quartz_timers_after_insert
 CREATE FUNCTION quartz."quartz_timers_after_insert"()
 	RETURNS TRIGGER
 	LANGUAGE c
 	AS 'MODULE_PATHNAME', 'quartz_timers_after_insert_wrapper';
 
--- src/lib.rs:47
+-- src/lib.rs:57
 -- quartz::quartz::quartz_timers_after_delete
 CREATE FUNCTION quartz."quartz_timers_after_delete"()
 	RETURNS TRIGGER
 	LANGUAGE c
 	AS 'MODULE_PATHNAME', 'quartz_timers_after_delete_wrapper';
 
--- src/lib.rs:76
+-- src/lib.rs:93
 -- quartz::quartz::deactivate_timers
 CREATE  FUNCTION quartz."deactivate_timers"(
 	"rel" TEXT /* &str */
@@ -58,16 +58,167 @@ STRICT
 LANGUAGE c /* Rust */
 AS 'MODULE_PATHNAME', 'deactivate_timers_wrapper';
 
--- src/lib.rs:88
+-- src/lib.rs:114
 -- quartz::quartz::create_timers_table
 CREATE  FUNCTION quartz."create_timers_table"(
-	"rel" TEXT /* &str */
+	"rel" TEXT, /* &str */
+	"payload" bool DEFAULT false /* bool */
 ) RETURNS void
 STRICT
 LANGUAGE c /* Rust */
 AS 'MODULE_PATHNAME', 'create_timers_table_wrapper';
 
--- src/lib.rs:64
+-- src/lib.rs:146
+-- quartz::quartz::register_timers_table
+CREATE  FUNCTION quartz."register_timers_table"(
+	"rel" TEXT, /* &str */
+	"mapping" jsonb DEFAULT '{}', /* pgrx::datum::json::JsonB */
+	"add_columns" bool DEFAULT false /* bool */
+) RETURNS void
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'register_timers_table_wrapper';
+
+-- src/lib.rs:165
+-- quartz::quartz::drop_timers_table
+CREATE  FUNCTION quartz."drop_timers_table"(
+	"rel" TEXT, /* &str */
+	"keep_data" bool DEFAULT false /* bool */
+) RETURNS void
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'drop_timers_table_wrapper';
+
+-- src/lib.rs:185
+-- quartz::quartz::set_timer_callback
+CREATE  FUNCTION quartz."set_timer_callback"(
+	"rel" TEXT, /* &str */
+	"callback" TEXT /* core::option::Option<&str> */
+) RETURNS void
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'set_timer_callback_wrapper';
+
+-- src/lib.rs:211
+-- quartz::quartz::set_timer_notify_channel
+CREATE  FUNCTION quartz."set_timer_notify_channel"(
+	"rel" TEXT, /* &str */
+	"channel" TEXT /* core::option::Option<&str> */
+) RETURNS void
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'set_timer_notify_channel_wrapper';
+
+-- src/lib.rs:236
+-- quartz::quartz::set_retry_policy
+CREATE  FUNCTION quartz."set_retry_policy"(
+	"rel" TEXT, /* &str */
+	"max_attempts" INT, /* i32 */
+	"backoff" interval DEFAULT '1 second', /* pgrx::datum::interval::Interval */
+	"backoff_max" interval DEFAULT '1 hour', /* pgrx::datum::interval::Interval */
+	"jitter" double precision DEFAULT 0.1, /* f64 */
+	"redeliver" bool DEFAULT false /* bool */
+) RETURNS void
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'set_retry_policy_wrapper';
+
+-- src/lib.rs:271
+-- quartz::quartz::set_misfire_policy
+CREATE  FUNCTION quartz."set_misfire_policy"(
+	"rel" TEXT, /* &str */
+	"policy" TEXT, /* &str */
+	"threshold" interval DEFAULT '1 minute', /* pgrx::datum::interval::Interval */
+	"key" TEXT DEFAULT NULL, /* core::option::Option<&str> */
+	"rate" double precision DEFAULT NULL /* core::option::Option<f64> */
+) RETURNS void
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'set_misfire_policy_wrapper';
+
+-- src/lib.rs:292
+-- quartz::quartz::complete_timer
+CREATE  FUNCTION quartz."complete_timer"(
+	"rel" TEXT, /* &str */
+	"id" bigint /* i64 */
+) RETURNS void
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'complete_timer_wrapper';
+
+-- src/lib.rs:308
+-- quartz::quartz::complete_timers
+CREATE  FUNCTION quartz."complete_timers"(
+	"rel" TEXT, /* &str */
+	"ids" bigint[] /* alloc::vec::Vec<i64> */
+) RETURNS void
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'complete_timers_wrapper';
+
+-- src/lib.rs:324
+-- quartz::quartz::scheduled_timers
+CREATE  FUNCTION quartz."scheduled_timers"() RETURNS TABLE (
+	"relid" oid,  /* pgrx_pg_sys::submodules::oids::Oid */
+	"id" bigint,  /* i64 */
+	"expires_at" timestamp with time zone,  /* pgrx::datum::time_stamp_with_timezone::TimestampWithTimeZone */
+	"remaining" interval  /* pgrx::datum::interval::Interval */
+)
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'scheduled_timers_wrapper';
+
+-- src/lib.rs:340
+-- quartz::quartz::timer_stats
+CREATE  FUNCTION quartz."timer_stats"() RETURNS TABLE (
+	"relid" oid,  /* core::option::Option<pgrx_pg_sys::submodules::oids::Oid> */
+	"created" bigint,  /* i64 */
+	"fired" bigint,  /* i64 */
+	"failed" bigint,  /* i64 */
+	"cancelled" bigint,  /* i64 */
+	"missed" bigint,  /* i64 */
+	"fire_latency_histogram" bigint[],  /* alloc::vec::Vec<i64> */
+	"timer_queue_overflows" bigint,  /* core::option::Option<i64> */
+	"timer_queue_depth" bigint,  /* core::option::Option<i64> */
+	"worker_queue_overflows" bigint,  /* core::option::Option<i64> */
+	"worker_queue_depth" bigint  /* core::option::Option<i64> */
+)
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'timer_stats_wrapper';
+
+-- src/lib.rs:362
+-- quartz::quartz::reset_stats
+CREATE  FUNCTION quartz."reset_stats"() RETURNS void
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'reset_stats_wrapper';
+
+-- src/lib.rs:372
+-- quartz::quartz::timers_table_dropped
+CREATE  FUNCTION quartz."timers_table_dropped"(
+	"relid" oid /* pgrx_pg_sys::submodules::oids::Oid */
+) RETURNS void
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'timers_table_dropped_wrapper';
+
+-- src/lib.rs:383
+-- quartz::quartz::check_timers_table
+CREATE  FUNCTION quartz."check_timers_table"(
+	"relid" oid /* pgrx_pg_sys::submodules::oids::Oid */
+) RETURNS void
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'check_timers_table_wrapper';
+
+-- src/lib.rs:394
+-- quartz::quartz::is_valid_cron
+CREATE  FUNCTION quartz."is_valid_cron"(
+	"expression" TEXT /* &str */
+) RETURNS bool /* bool */
+IMMUTABLE STRICT PARALLEL SAFE
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'is_valid_cron_wrapper';
+
+-- src/lib.rs:78
 -- quartz::quartz::activate_timers
 CREATE  FUNCTION quartz."activate_timers"(
 	"rel" TEXT /* &str */
@@ -76,13 +227,28 @@ STRICT
 LANGUAGE c /* Rust */
 AS 'MODULE_PATHNAME', 'activate_timers_wrapper';
 
--- src/lib.rs:15
+-- src/lib.rs:20
 ---
 --- src/init.sql
 ---
 
 create table quartz.timer_relations (
-    relid oid primary key
+    relid oid primary key,
+    callback regprocedure,
+    max_attempts integer not null default 1 check (max_attempts >= 1),
+    retry_backoff interval not null default '1 second',
+    retry_backoff_max interval not null default '1 hour',
+    retry_jitter double precision not null default 0.1 check (retry_jitter between 0 and 1),
+    notify_channel text,
+    redeliver boolean not null default false,
+    misfire_policy text not null default 'fire_all'
+        check (misfire_policy in ('fire_all', 'fire_latest', 'skip', 'catch_up')),
+    misfire_threshold interval not null default '1 minute' check (misfire_threshold >= interval '0'),
+    misfire_key text check (misfire_key is null or misfire_policy = 'fire_latest'),
+    misfire_rate double precision check (misfire_rate > 0),
+    column_mapping jsonb not null,
+    enabled boolean not null default true,
+    check ((misfire_policy = 'catch_up') = (misfire_rate is not null))
 );
 
 create function quartz.check_relation_is_table()
@@ -106,3 +272,121 @@ create trigger check_relation_is_table
     before insert or update on quartz.timer_relations
     for each row execute function quartz.check_relation_is_table();
 
+create table quartz.spilled_events (
+    id bigserial primary key,
+    relid oid not null,
+    timer_id bigint
+);
+
+-- Event triggers run as whoever issues the DDL, who need not have any
+-- privileges on quartz.timer_relations, so their functions are security
+-- definers.
+create function quartz.on_sql_drop()
+returns event_trigger
+as $$
+declare
+    dropped record;
+begin
+    -- The extension itself is being dropped.
+    if to_regclass('quartz.timer_relations') is null then
+        return;
+    end if;
+
+    for dropped in
+        delete from quartz.timer_relations tr
+        using pg_event_trigger_dropped_objects() o
+        where o.object_type = 'table' and o.objid = tr.relid
+        returning tr.relid, tr.enabled
+    loop
+        -- Deactivated tables are not tracked.
+        if dropped.enabled then
+            perform quartz.timers_table_dropped(dropped.relid);
+        end if;
+    end loop;
+end;
+$$ language plpgsql security definer set search_path = pg_catalog, pg_temp;
+
+create event trigger quartz_sql_drop
+    on sql_drop
+    execute function quartz.on_sql_drop();
+
+create function quartz.on_ddl_command_end()
+returns event_trigger
+as $$
+declare
+    altered record;
+begin
+    for altered in
+        select distinct c.objid
+        from pg_event_trigger_ddl_commands() c
+        join quartz.timer_relations tr on tr.relid = c.objid
+        where c.object_type = 'table'
+    loop
+        perform quartz.check_timers_table(altered.objid);
+    end loop;
+end;
+$$ language plpgsql security definer set search_path = pg_catalog, pg_temp;
+
+create event trigger quartz_ddl_command_end
+    on ddl_command_end
+    when tag in ('ALTER TABLE')
+    execute function quartz.on_ddl_command_end();
+
+create type quartz.timer_id as (
+    relid regclass,
+    id bigint
+);
+
+comment on type quartz.timer_id is
+    'Identifies a timer across all timers tables, by its table and its ID in that table.';
+
+create function quartz.complete_timer(timer quartz.timer_id)
+returns void
+as $$
+begin
+    if timer.relid is null or timer.id is null then
+        raise exception 'quartz.complete_timer(): timer % is incomplete', timer;
+    end if;
+
+    perform quartz.complete_timer(timer.relid::text, timer.id);
+end;
+$$ language plpgsql strict;
+
+create function quartz.complete_timers(timers quartz.timer_id[])
+returns void
+as $$
+declare
+    completed record;
+begin
+    if exists (select 1 from unnest(timers) t where t.relid is null or t.id is null) then
+        raise exception 'quartz.complete_timers(): timers must not be incomplete';
+    end if;
+
+    -- Tables are completed in a fixed order, so that concurrent calls lock
+    -- their timers in the same order.
+    for completed in
+        select t.relid, array_agg(t.id order by t.id) as ids
+        from unnest(timers) t
+        group by t.relid
+        order by t.relid
+    loop
+        perform quartz.complete_timers(completed.relid::text, completed.ids);
+    end loop;
+end;
+$$ language plpgsql strict;
+
+-- src/lib.rs:25
+---
+--- src/stats.sql
+---
+
+create view quartz.stats as
+    select * from quartz.timer_stats();
+
+comment on view quartz.stats is
+    'Runtime statistics of the timer subsystem of the current database, per timers table. '
+    'The row without relid holds the totals and the queue statistics.';
+
+comment on column quartz.stats.fire_latency_histogram is
+    'Number of fired timers by delay after expires_at, in buckets '
+    'below 1ms, 10ms, 100ms, 1s, 10s, 1min, 10min and above 10min.';
@@ -0,0 +1,14 @@
+---
+--- src/stats.sql
+---
+
+create view quartz.stats as
+    select * from quartz.timer_stats();
+
+comment on view quartz.stats is
+    'Runtime statistics of the timer subsystem of the current database, per timers table. '
+    'The row without relid holds the totals and the queue statistics.';
+
+comment on column quartz.stats.fire_latency_histogram is
+    'Number of fired timers by delay after expires_at, in buckets '
+    'below 1ms, 10ms, 100ms, 1s, 10s, 1min, 10min and above 10min.';
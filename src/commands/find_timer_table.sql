@@ -1,7 +1,35 @@
 select
     tr.relid,
     pn.nspname::text as schema_name,
-    pc.relname::text as table_name
+    pc.relname::text as table_name,
+    tr.callback::oid::regproc::text as callback,
+    tr.max_attempts,
+    (extract(epoch from tr.retry_backoff) * 1000)::bigint as retry_backoff_ms,
+    (extract(epoch from tr.retry_backoff_max) * 1000)::bigint as retry_backoff_max_ms,
+    tr.retry_jitter,
+    tr.notify_channel,
+    tr.redeliver,
+    tr.misfire_policy,
+    tr.misfire_threshold,
+    tr.misfire_key,
+    tr.misfire_rate,
+    array[
+        tr.column_mapping ->> 'id',
+        tr.column_mapping ->> 'expires_at',
+        tr.column_mapping ->> 'fired_at',
+        tr.column_mapping ->> 'completed_at',
+        tr.column_mapping ->> 'attempts',
+        tr.column_mapping ->> 'last_error',
+        tr.column_mapping ->> 'repeat_every',
+        tr.column_mapping ->> 'schedule',
+        tr.column_mapping ->> 'repeat_until',
+        tr.column_mapping ->> 'max_occurrences',
+        tr.column_mapping ->> 'occurrences',
+        tr.column_mapping ->> 'missed_at',
+        tr.column_mapping ->> 'payload'
+    ] as column_mapping,
+    tr.enabled,
+    pc.relowner as owner
 from quartz.timer_relations tr
 join pg_class pc on tr.relid = pc.oid
 join pg_namespace pn on pc.relnamespace = pn.oid
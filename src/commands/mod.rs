@@ -2,23 +2,108 @@
 
 use pgrx::pg_sys::Oid;
 use pgrx::prelude::*;
+use pgrx::spi;
 use pgrx::spi::Error as SpiError;
 use pgrx::spi::SpiClient;
+use pgrx::spi::SpiHeapTupleData;
 
+use crate::cron::CronSchedule;
 use crate::timestamp;
+use crate::timestamp::CalendarInterval;
+use crate::types::ColumnMapping;
+use crate::types::MisfirePolicy;
+use crate::types::Recurrence;
+use crate::types::RetryPolicy;
+use crate::types::TimerId;
 use crate::types::TimerRow;
 
 pub struct TimerTableData {
     pub relid: Oid,
     pub schema: String,
     pub table: String,
+    pub callback: Option<String>,
+    pub retry_policy: RetryPolicy,
+    pub notify_channel: Option<String>,
+    pub misfire_policy: MisfirePolicy,
+    pub misfire_key: Option<String>,
+    pub columns: ColumnMapping,
+    pub enabled: bool,
+    pub owner: Oid,
+}
+
+impl TimerTableData {
+    /// The schema-qualified name of the table, quoted for use in queries.
+    pub fn qualified_name(&self) -> String {
+        spi::quote_qualified_identifier(self.schema.as_str(), self.table.as_str())
+    }
+}
+
+/// Find the databases that quartz may be installed in, as OID and name.
+pub fn find_databases(client: &SpiClient<'_>) -> Result<Vec<(Oid, String)>, SpiError> {
+    let query = r#"
+        select oid, datname::text
+        from pg_catalog.pg_database
+        where datallowconn and not datistemplate
+    "#;
+
+    let tuples = client.select(query, None, None)?;
+
+    let mut vec = Vec::with_capacity(tuples.len());
+
+    for tuple in tuples {
+        let oid = tuple
+            .get::<Oid>(1)
+            .expect("commands::find_databases(): no oid")
+            .expect("commands::find_databases(): oid is null");
+        let name = tuple
+            .get::<String>(2)
+            .expect("commands::find_databases(): no datname")
+            .expect("commands::find_databases(): datname is null");
+
+        vec.push((oid, name));
+    }
+
+    Ok(vec)
+}
+
+pub fn find_database_name(client: &SpiClient<'_>, oid: Oid) -> Result<Option<String>, SpiError> {
+    let query = r#"
+        select datname::text
+        from pg_catalog.pg_database
+        where oid = $1
+    "#;
+
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::OIDOID), oid.into_datum())];
+
+    let tuples = client.select(query, None, Some(args))?;
+
+    if tuples.is_empty() {
+        return Ok(None);
+    }
+
+    tuples.first().get_one::<String>()
+}
+
+/// Check whether the quartz extension is installed in the current database.
+pub fn is_extension_installed(client: &SpiClient<'_>) -> Result<bool, SpiError> {
+    let query = r#"
+        select exists (
+            select 1 from pg_catalog.pg_extension where extname = 'quartz'
+        )
+    "#;
+
+    client
+        .select(query, None, None)?
+        .first()
+        .get_one::<bool>()
+        .map(|value| value.unwrap_or(false))
 }
 
 pub fn find_timer_table(
     client: &SpiClient<'_>,
     oid: Oid,
 ) -> Result<Option<TimerTableData>, SpiError> {
-    const QUERY: &'static str = include_str!("find_timer_table.sql");
+    const QUERY: &str = include_str!("find_timer_table.sql");
 
     let args = vec![(PgOid::Custom(pgrx::pg_sys::OIDOID), oid.into_datum())];
 
@@ -31,65 +116,125 @@ pub fn find_timer_table(
         None => return Ok(None),
     };
 
+    Ok(Some(timer_table_from_tuple(&tuple)))
+}
+
+pub fn find_timer_tables(client: &SpiClient<'_>) -> Result<Vec<TimerTableData>, SpiError> {
+    const QUERY: &str = include_str!("find_timer_tables.sql");
+
+    let tuples = client.select(QUERY, None, None)?;
+
+    let mut vec = Vec::with_capacity(tuples.len());
+
+    for tuple in tuples {
+        vec.push(timer_table_from_tuple(&tuple));
+    }
+
+    Ok(vec)
+}
+
+fn timer_table_from_tuple(tuple: &SpiHeapTupleData) -> TimerTableData {
     // ordinal position is 1-based
 
     let relid = tuple
         .get::<Oid>(1)
-        .expect("commands::find_timer_table(): no relid")
-        .expect("commands::find_timer_table(): relid is null");
+        .expect("commands::timer_table_from_tuple(): no relid")
+        .expect("commands::timer_table_from_tuple(): relid is null");
     let schema = tuple
         .get::<String>(2)
-        .expect("commands::find_timer_table(): no schema")
-        .expect("commands::find_timer_table(): schema is null");
+        .expect("commands::timer_table_from_tuple(): no schema")
+        .expect("commands::timer_table_from_tuple(): schema is null");
     let table = tuple
         .get::<String>(3)
-        .expect("commands::find_timer_table(): no table")
-        .expect("commands::find_timer_table(): table is null");
+        .expect("commands::timer_table_from_tuple(): no table")
+        .expect("commands::timer_table_from_tuple(): table is null");
+    let callback = tuple
+        .get::<String>(4)
+        .expect("commands::timer_table_from_tuple(): no callback");
+    let max_attempts = tuple
+        .get::<i32>(5)
+        .expect("commands::timer_table_from_tuple(): no max_attempts")
+        .expect("commands::timer_table_from_tuple(): max_attempts is null");
+    let backoff = tuple
+        .get::<i64>(6)
+        .expect("commands::timer_table_from_tuple(): no retry_backoff")
+        .map(chrono::Duration::milliseconds)
+        .expect("commands::timer_table_from_tuple(): retry_backoff is null");
+    let backoff_max = tuple
+        .get::<i64>(7)
+        .expect("commands::timer_table_from_tuple(): no retry_backoff_max")
+        .map(chrono::Duration::milliseconds)
+        .expect("commands::timer_table_from_tuple(): retry_backoff_max is null");
+    let jitter = tuple
+        .get::<f64>(8)
+        .expect("commands::timer_table_from_tuple(): no retry_jitter")
+        .expect("commands::timer_table_from_tuple(): retry_jitter is null");
+    let notify_channel = tuple
+        .get::<String>(9)
+        .expect("commands::timer_table_from_tuple(): no notify_channel");
+    let redeliver = tuple
+        .get::<bool>(10)
+        .expect("commands::timer_table_from_tuple(): no redeliver")
+        .expect("commands::timer_table_from_tuple(): redeliver is null");
+    let misfire_action = tuple
+        .get::<String>(11)
+        .expect("commands::timer_table_from_tuple(): no misfire_policy")
+        .expect("commands::timer_table_from_tuple(): misfire_policy is null");
+    let misfire_threshold = tuple
+        .get::<Interval>(12)
+        .expect("commands::timer_table_from_tuple(): no misfire_threshold")
+        .map(CalendarInterval::from)
+        .expect("commands::timer_table_from_tuple(): misfire_threshold is null");
+    let misfire_key = tuple
+        .get::<String>(13)
+        .expect("commands::timer_table_from_tuple(): no misfire_key");
+    let misfire_rate = tuple
+        .get::<f64>(14)
+        .expect("commands::timer_table_from_tuple(): no misfire_rate");
+    let columns = tuple
+        .get::<Vec<Option<String>>>(15)
+        .expect("commands::timer_table_from_tuple(): no column_mapping")
+        .map(ColumnMapping::from_columns)
+        .expect("commands::timer_table_from_tuple(): column_mapping is null")
+        .expect("commands::timer_table_from_tuple(): invalid column_mapping");
+    let enabled = tuple
+        .get::<bool>(16)
+        .expect("commands::timer_table_from_tuple(): no enabled")
+        .expect("commands::timer_table_from_tuple(): enabled is null");
+    let owner = tuple
+        .get::<Oid>(17)
+        .expect("commands::timer_table_from_tuple(): no owner")
+        .expect("commands::timer_table_from_tuple(): owner is null");
 
-    Ok(Some(TimerTableData {
+    let misfire_policy = MisfirePolicy::try_new(misfire_action.as_str(), misfire_rate, misfire_threshold)
+        .expect("commands::timer_table_from_tuple(): invalid misfire policy");
+
+    TimerTableData {
         relid,
         schema,
         table,
-    }))
-}
-
-pub fn find_timer_tables(client: &SpiClient<'_>) -> Result<Vec<TimerTableData>, SpiError> {
-    const QUERY: &'static str = include_str!("find_timer_tables.sql");
-
-    let tuples = client.select(QUERY, None, None)?;
-
-    let mut vec = Vec::with_capacity(tuples.len());
-
-    for tuple in tuples {
-        // ordinal position is 1-based
-
-        let relid = tuple
-            .get::<Oid>(1)
-            .expect("commands::find_timer_tables(): no relid")
-            .expect("commands::find_timer_tables(): relid is null");
-        let schema = tuple
-            .get::<String>(2)
-            .expect("commands::find_timer_tables(): no schema")
-            .expect("commands::find_timer_tables(): schema is null");
-        let table = tuple
-            .get::<String>(3)
-            .expect("commands::find_timer_tables(): no table")
-            .expect("commands::find_timer_tables(): table is null");
-
-        vec.push(TimerTableData {
-            relid,
-            schema,
-            table,
-        });
+        callback,
+        retry_policy: RetryPolicy {
+            max_attempts,
+            backoff,
+            backoff_max,
+            jitter,
+            redeliver,
+        },
+        notify_channel,
+        misfire_policy,
+        misfire_key,
+        columns,
+        enabled,
+        owner,
     }
-
-    Ok(vec)
 }
 
 pub fn find_timers_in_table(
     client: &SpiClient<'_>,
     schema: &str,
     table: &str,
+    columns: &ColumnMapping,
 ) -> Result<Vec<TimerRow>, SpiError> {
     // fixme: this returns all timers, which is not well optimized in case
     // there are many of them. callers either want un-fired timers or
@@ -97,9 +242,10 @@ pub fn find_timers_in_table(
 
     let query = format!(
         r#"
-        select id, expires_at, fired_at, completed_at from "{}"."{}"
+        select {}
+        from {}
         "#,
-        schema, table
+        self::timer_row_columns(columns), spi::quote_qualified_identifier(schema, table)
     );
 
     let tuples = client.select(query.as_str(), None, None)?;
@@ -107,60 +253,685 @@ pub fn find_timers_in_table(
     let mut vec = Vec::with_capacity(tuples.len());
 
     for tuple in tuples {
-        // ordinal position is 1-based
-
-        let id = tuple
-            .get::<i64>(1)
-            .expect("commands::find_timers_in_table(): no id")
-            .expect("commands::find_timers_in_table(): id is null");
-        let expires_at = tuple
-            .get::<TimestampWithTimeZone>(2)
-            .expect("commands::find_timers_in_table(): no expires_at")
-            .map(timestamp::pg_to_chrono)
-            .expect("commands::find_timers_in_table(): expires_at is null");
-        let fired_at = tuple
-            .get::<TimestampWithTimeZone>(3)
-            .expect("commands::find_timers_in_table(): no fired_at")
-            .map(timestamp::pg_to_chrono);
-        let completed_at = tuple
-            .get::<TimestampWithTimeZone>(4)
-            .expect("commands::find_timers_in_table(): no completed_at")
-            .map(timestamp::pg_to_chrono);
-
-        vec.push(TimerRow {
-            id,
-            expires_at,
-            fired_at,
-            completed_at,
-        });
+        vec.push(timer_row_from_tuple(&tuple));
     }
 
     Ok(vec)
 }
 
+pub fn find_timer(
+    client: &SpiClient<'_>,
+    schema: &str,
+    table: &str,
+    columns: &ColumnMapping,
+    id: i64,
+) -> Result<Option<TimerRow>, SpiError> {
+    let query = format!(
+        r#"
+        select {}
+        from {}
+        where {} = $1
+        "#,
+        self::timer_row_columns(columns),
+        spi::quote_qualified_identifier(schema, table),
+        spi::quote_identifier(&columns.id)
+    );
+
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::INT8OID), id.into_datum())];
+
+    let timer = client
+        .select(query.as_str(), None, Some(args))?
+        .first()
+        .get_heap_tuple()?
+        .map(|tuple| timer_row_from_tuple(&tuple));
+
+    Ok(timer)
+}
+
+pub fn find_timer_for_update(
+    client: &mut SpiClient<'_>,
+    schema: &str,
+    table: &str,
+    columns: &ColumnMapping,
+    id: i64,
+) -> Result<Option<TimerRow>, SpiError> {
+    let query = format!(
+        r#"
+        select {}
+        from {}
+        where {} = $1
+        for update
+        "#,
+        self::timer_row_columns(columns),
+        spi::quote_qualified_identifier(schema, table),
+        spi::quote_identifier(&columns.id)
+    );
+
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::INT8OID), id.into_datum())];
+
+    let timer = client
+        .update(query.as_str(), None, Some(args))?
+        .first()
+        .get_heap_tuple()?
+        .map(|tuple| timer_row_from_tuple(&tuple));
+
+    Ok(timer)
+}
+
+/// The columns that timer rows are selected with, in the order that
+/// `timer_row_from_tuple` expects. Roles that the table does not map are
+/// selected as the value they read as.
+fn timer_row_columns(columns: &ColumnMapping) -> String {
+    let optional = |column: &Option<String>, value: &str| match column {
+        Some(column) => spi::quote_identifier(column),
+        None => value.to_string(),
+    };
+
+    [
+        spi::quote_identifier(&columns.id),
+        spi::quote_identifier(&columns.expires_at),
+        spi::quote_identifier(&columns.fired_at),
+        spi::quote_identifier(&columns.completed_at),
+        optional(&columns.attempts, "0"),
+        optional(&columns.repeat_every, "null::interval"),
+        optional(&columns.schedule, "null::text"),
+        optional(&columns.repeat_until, "null::timestamp with time zone"),
+        optional(&columns.max_occurrences, "null::integer"),
+        optional(&columns.occurrences, "0"),
+        optional(&columns.missed_at, "null::timestamp with time zone"),
+    ]
+    .join(", ")
+}
+
+/// The assignment that counts an occurrence of a timer, if its table counts
+/// them.
+fn count_occurrence(columns: &ColumnMapping) -> String {
+    match &columns.occurrences {
+        Some(column) => format!(", {0} = {0} + 1", spi::quote_identifier(column)),
+        None => String::new(),
+    }
+}
+
+fn timer_row_from_tuple(tuple: &SpiHeapTupleData) -> TimerRow {
+    // ordinal position is 1-based
+
+    let id = tuple
+        .get::<i64>(1)
+        .expect("commands::timer_row_from_tuple(): no id")
+        .expect("commands::timer_row_from_tuple(): id is null");
+    let expires_at = tuple
+        .get::<TimestampWithTimeZone>(2)
+        .expect("commands::timer_row_from_tuple(): no expires_at")
+        .map(timestamp::pg_to_chrono)
+        .expect("commands::timer_row_from_tuple(): expires_at is null");
+    let fired_at = tuple
+        .get::<TimestampWithTimeZone>(3)
+        .expect("commands::timer_row_from_tuple(): no fired_at")
+        .map(timestamp::pg_to_chrono);
+    let completed_at = tuple
+        .get::<TimestampWithTimeZone>(4)
+        .expect("commands::timer_row_from_tuple(): no completed_at")
+        .map(timestamp::pg_to_chrono);
+    let attempts = tuple
+        .get::<i32>(5)
+        .expect("commands::timer_row_from_tuple(): no attempts")
+        .expect("commands::timer_row_from_tuple(): attempts is null");
+    let repeat_every = tuple
+        .get::<Interval>(6)
+        .expect("commands::timer_row_from_tuple(): no repeat_every")
+        .map(CalendarInterval::from);
+    let schedule = tuple
+        .get::<String>(7)
+        .expect("commands::timer_row_from_tuple(): no schedule")
+        .map(|value| CronSchedule::parse(&value))
+        .transpose()
+        .expect("commands::timer_row_from_tuple(): invalid schedule");
+    let repeat_until = tuple
+        .get::<TimestampWithTimeZone>(8)
+        .expect("commands::timer_row_from_tuple(): no repeat_until")
+        .map(timestamp::pg_to_chrono);
+    let max_occurrences = tuple
+        .get::<i32>(9)
+        .expect("commands::timer_row_from_tuple(): no max_occurrences");
+    let occurrences = tuple
+        .get::<i32>(10)
+        .expect("commands::timer_row_from_tuple(): no occurrences")
+        .expect("commands::timer_row_from_tuple(): occurrences is null");
+    let missed_at = tuple
+        .get::<TimestampWithTimeZone>(11)
+        .expect("commands::timer_row_from_tuple(): no missed_at")
+        .map(timestamp::pg_to_chrono);
+
+    let recurrence = Recurrence::try_new(repeat_every, schedule, repeat_until, max_occurrences)
+        .expect("commands::timer_row_from_tuple(): invalid recurrence");
+
+    TimerRow {
+        id,
+        expires_at,
+        fired_at,
+        completed_at,
+        attempts,
+        recurrence,
+        occurrences,
+        missed_at,
+    }
+}
+
 pub fn mark_timer_as_fired(
     client: &mut SpiClient<'_>,
     schema: &str,
     table: &str,
+    columns: &ColumnMapping,
+    id: i64,
+) -> Result<bool, SpiError> {
+    let query = format!(
+        r#"
+        update {table}
+        set {fired_at} = now(){count_occurrence}
+        where {id} = $1 and {fired_at} is null
+        returning {id}
+        "#,
+        table = spi::quote_qualified_identifier(schema, table),
+        id = spi::quote_identifier(&columns.id),
+        fired_at = spi::quote_identifier(&columns.fired_at),
+        count_occurrence = self::count_occurrence(columns),
+    );
+
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::INT8OID), id.into_datum())];
+
+    let tuples = client.update(query.as_str(), None, Some(args))?;
+
+    Ok(!tuples.is_empty())
+}
+
+/// Advance a recurring timer to its next expiration, instead of marking it as
+/// fired.
+pub fn advance_recurring_timer(
+    client: &mut SpiClient<'_>,
+    schema: &str,
+    table: &str,
+    columns: &ColumnMapping,
+    id: i64,
+    next_expires_at: chrono::DateTime<chrono::Local>,
+) -> Result<(), SpiError> {
+    let query = format!(
+        r#"
+        update {table}
+        set {expires_at} = $2{count_occurrence}
+        where {id} = $1
+        "#,
+        table = spi::quote_qualified_identifier(schema, table),
+        id = spi::quote_identifier(&columns.id),
+        expires_at = spi::quote_identifier(&columns.expires_at),
+        count_occurrence = self::count_occurrence(columns),
+    );
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::INT8OID), id.into_datum()),
+        (
+            PgOid::Custom(pgrx::pg_sys::TIMESTAMPTZOID),
+            timestamp::chrono_to_pg(next_expires_at).into_datum(),
+        ),
+    ];
+
+    client.update(query.as_str(), None, Some(args)).map(|_| ())
+}
+
+/// Move a failed timer to the time of its next attempt.
+///
+/// The expiration of a recurring timer moves as well, so its next occurrences
+/// are computed from the retry.
+pub fn retry_timer_at(
+    client: &mut SpiClient<'_>,
+    schema: &str,
+    table: &str,
+    columns: &ColumnMapping,
+    id: i64,
+    retry_at: chrono::DateTime<chrono::Local>,
+) -> Result<(), SpiError> {
+    let query = format!(
+        r#"
+        update {table}
+        set {expires_at} = $2
+        where {id} = $1
+        "#,
+        table = spi::quote_qualified_identifier(schema, table),
+        id = spi::quote_identifier(&columns.id),
+        expires_at = spi::quote_identifier(&columns.expires_at),
+    );
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::INT8OID), id.into_datum()),
+        (
+            PgOid::Custom(pgrx::pg_sys::TIMESTAMPTZOID),
+            timestamp::chrono_to_pg(retry_at).into_datum(),
+        ),
+    ];
+
+    client.update(query.as_str(), None, Some(args)).map(|_| ())
+}
+
+/// Notify listeners on the given channel that a timer has fired.
+///
+/// The payload of the timer is included, or null if the table does not map the
+/// payload role. Notifications are only delivered once the firing transaction
+/// commits.
+///
+/// The stored `fired_at` of the timer is sent. Occurrences of a recurring timer
+/// before its last one do not store it, and fired at the same `now()`.
+pub fn notify_timer_fired(
+    client: &mut SpiClient<'_>,
+    channel: &str,
+    schema: &str,
+    table: &str,
+    columns: &ColumnMapping,
+    timer_id: TimerId,
+    expires_at: chrono::DateTime<chrono::Local>,
+) -> Result<(), SpiError> {
+    let query = format!(
+        r#"
+        select pg_notify($1, json_build_object(
+            'timer', $6::text,
+            'schema', $2::text,
+            'table', $3::text,
+            'id', $4::bigint,
+            'expires_at', $5::timestamp with time zone,
+            'fired_at', coalesce(t.{fired_at}, now()),
+            'payload', {payload}
+        )::text)
+        from {table} t
+        where t.{id} = $4
+        "#,
+        payload = columns
+            .payload
+            .as_ref()
+            .map(|column| format!("t.{}", spi::quote_identifier(column)))
+            .unwrap_or_else(|| "null::jsonb".to_string()),
+        table = spi::quote_qualified_identifier(schema, table),
+        id = spi::quote_identifier(&columns.id),
+        fired_at = spi::quote_identifier(&columns.fired_at),
+    );
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), channel.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), schema.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), table.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::INT8OID), timer_id.id.into_datum()),
+        (
+            PgOid::Custom(pgrx::pg_sys::TIMESTAMPTZOID),
+            timestamp::chrono_to_pg(expires_at).into_datum(),
+        ),
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), timer_id.to_string().into_datum()),
+    ];
+
+    client.update(query.as_str(), None, Some(args)).map(|_| ())
+}
+
+/// Mark a timer that misfired as missed, instead of firing it.
+///
+/// Only tables that map the missed_at role may skip timers, see
+/// `set_misfire_policy`.
+pub fn mark_timer_as_missed(
+    client: &mut SpiClient<'_>,
+    schema: &str,
+    table: &str,
+    columns: &ColumnMapping,
     id: i64,
 ) -> Result<(), SpiError> {
+    let missed_at = columns
+        .missed_at
+        .as_ref()
+        .expect("commands::mark_timer_as_missed(): table has no missed_at column");
+
     let query = format!(
         r#"
-        update "{}"."{}"
-        set fired_at = localtimestamp
-        where id = $1
-        returning id
+        update {}
+        set {} = now()
+        where {} = $1
         "#,
-        schema, table
+        spi::quote_qualified_identifier(schema, table),
+        spi::quote_identifier(missed_at),
+        spi::quote_identifier(&columns.id)
     );
 
     let args = vec![(PgOid::Custom(pgrx::pg_sys::INT8OID), id.into_datum())];
 
+    client.update(query.as_str(), None, Some(args)).map(|_| ())
+}
+
+/// Move a recurring timer that misfired to its next expiration, without
+/// counting the missed occurrence.
+pub fn skip_recurring_timer(
+    client: &mut SpiClient<'_>,
+    schema: &str,
+    table: &str,
+    columns: &ColumnMapping,
+    id: i64,
+    next_expires_at: chrono::DateTime<chrono::Local>,
+) -> Result<(), SpiError> {
+    let query = format!(
+        r#"
+        update {}
+        set {} = $2
+        where {} = $1
+        "#,
+        spi::quote_qualified_identifier(schema, table),
+        spi::quote_identifier(&columns.expires_at),
+        spi::quote_identifier(&columns.id)
+    );
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::INT8OID), id.into_datum()),
+        (
+            PgOid::Custom(pgrx::pg_sys::TIMESTAMPTZOID),
+            timestamp::chrono_to_pg(next_expires_at).into_datum(),
+        ),
+    ];
+
+    client.update(query.as_str(), None, Some(args)).map(|_| ())
+}
+
+/// Check whether a pending timer with the same key expired after the given
+/// one, and has misfired as well, i.e. expired more than `threshold` ago.
+///
+/// Without a key column, all timers of the table share the same key.
+pub fn has_later_misfired_timer(
+    client: &SpiClient<'_>,
+    schema: &str,
+    table: &str,
+    columns: &ColumnMapping,
+    key: Option<&str>,
+    id: i64,
+    threshold: CalendarInterval,
+) -> Result<bool, SpiError> {
+    let same_key = match key {
+        Some(key) => format!(
+            r#"and other.{0} is not distinct from this.{0}"#,
+            spi::quote_identifier(key)
+        ),
+        None => String::new(),
+    };
+
+    let not_missed = match &columns.missed_at {
+        Some(missed_at) => format!("and other.{} is null", spi::quote_identifier(missed_at)),
+        None => String::new(),
+    };
+
+    let query = format!(
+        r#"
+        select exists (
+            select 1
+            from {table} this
+            join {table} other
+                on (other.{expires_at}, other.{id}) > (this.{expires_at}, this.{id})
+            where this.{id} = $1
+            and other.{fired_at} is null
+            {not_missed}
+            and other.{expires_at} + $2 < now()
+            {same_key}
+        )
+        "#,
+        table = spi::quote_qualified_identifier(schema, table),
+        id = spi::quote_identifier(&columns.id),
+        expires_at = spi::quote_identifier(&columns.expires_at),
+        fired_at = spi::quote_identifier(&columns.fired_at),
+        not_missed = not_missed,
+        same_key = same_key,
+    );
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::INT8OID), id.into_datum()),
+        (
+            PgOid::Custom(pgrx::pg_sys::INTERVALOID),
+            Interval::from(threshold).into_datum(),
+        ),
+    ];
+
     client
-        .update(query.as_str(), None, Some(args))?
+        .select(query.as_str(), None, Some(args))?
+        .first()
+        .get_one::<bool>()
+        .map(|value| value.unwrap_or(false))
+}
+
+pub fn mark_timer_as_completed(
+    client: &mut SpiClient<'_>,
+    schema: &str,
+    table: &str,
+    columns: &ColumnMapping,
+    id: i64,
+) -> Result<(), SpiError> {
+    let query = format!(
+        r#"
+        update {}
+        set {} = now()
+        where {} = $1
+        "#,
+        spi::quote_qualified_identifier(schema, table),
+        spi::quote_identifier(&columns.completed_at),
+        spi::quote_identifier(&columns.id)
+    );
+
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::INT8OID), id.into_datum())];
+
+    client.update(query.as_str(), None, Some(args)).map(|_| ())
+}
+
+/// Record a failed attempt at firing a timer, returning the number of failed
+/// attempts so far.
+///
+/// Tables that do not map the attempts role do not count failures, so every
+/// failure is their first attempt.
+pub fn record_timer_failure(
+    client: &mut SpiClient<'_>,
+    schema: &str,
+    table: &str,
+    columns: &ColumnMapping,
+    id: i64,
+    error: &str,
+) -> Result<Option<i32>, SpiError> {
+    let mut assignments = Vec::new();
+
+    if let Some(attempts) = &columns.attempts {
+        assignments.push(format!("{0} = {0} + 1", spi::quote_identifier(attempts)));
+    }
+
+    if let Some(last_error) = &columns.last_error {
+        assignments.push(format!("{} = $2", spi::quote_identifier(last_error)));
+    }
+
+    let attempts = match &columns.attempts {
+        Some(attempts) => spi::quote_identifier(attempts),
+        None => "1".to_string(),
+    };
+
+    let query = if assignments.is_empty() {
+        format!(
+            r#"
+            select {attempts} from {table}
+            where {id} = $1
+            "#,
+            table = spi::quote_qualified_identifier(schema, table),
+            id = spi::quote_identifier(&columns.id),
+            attempts = attempts,
+        )
+    } else {
+        format!(
+            r#"
+            update {table}
+            set {assignments}
+            where {id} = $1
+            returning {attempts}
+            "#,
+            table = spi::quote_qualified_identifier(schema, table),
+            id = spi::quote_identifier(&columns.id),
+            assignments = assignments.join(", "),
+            attempts = attempts,
+        )
+    };
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::INT8OID), id.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), error.into_datum()),
+    ];
+
+    let tuples = client.update(query.as_str(), None, Some(args))?;
+
+    if tuples.is_empty() {
+        return Ok(None);
+    }
+
+    tuples.first().get_one::<i32>()
+}
+
+/// Invoke the callback of a timers table with the row of a timer.
+///
+/// Workers run as a superuser, so the callback runs as the owner of the table
+/// instead, as a security-restricted operation like `REFRESH MATERIALIZED
+/// VIEW` runs its query. Settings that the callback changes are reverted.
+pub fn invoke_timer_callback(
+    client: &mut SpiClient<'_>,
+    schema: &str,
+    table: &str,
+    columns: &ColumnMapping,
+    callback: &str,
+    owner: Oid,
+    id: i64,
+) -> Result<(), SpiError> {
+    // The callback name is the text representation of a regproc, so it is
+    // already quoted and schema-qualified where required.
+    let query = format!(
+        r#"
+        select {}(t) from {} t
+        where t.{} = $1
+        "#,
+        callback,
+        spi::quote_qualified_identifier(schema, table),
+        spi::quote_identifier(&columns.id)
+    );
+
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::INT8OID), id.into_datum())];
+
+    let mut user_id = pg_sys::InvalidOid;
+    let mut sec_context = 0;
+
+    unsafe {
+        pg_sys::GetUserIdAndSecContext(&mut user_id, &mut sec_context);
+        pg_sys::SetUserIdAndSecContext(
+            owner,
+            sec_context | pg_sys::SECURITY_RESTRICTED_OPERATION as i32,
+        );
+    }
+
+    let nest_level = unsafe { pg_sys::NewGUCNestLevel() };
+
+    // An error in the callback aborts the subtransaction that fires the timer,
+    // which restores the user and the settings as well.
+    let result = client.update(query.as_str(), None, Some(args)).map(|_| ());
+
+    unsafe {
+        pg_sys::AtEOXact_GUC(false, nest_level);
+        pg_sys::SetUserIdAndSecContext(user_id, sec_context);
+    }
+
+    result
+}
+
+/// Record an event that did not fit into the queue of the timer subsystem.
+///
+/// Only the table and the timer the event refers to are recorded. The timer
+/// subsystem brings them up to date with their rows when it takes the event.
+///
+/// Events are spilled by whoever changes a timers table, who need not have any
+/// privileges on the quartz schema, so the event is recorded as the owner of
+/// `quartz.spilled_events`, like a security definer function would.
+pub fn spill_timer_event(
+    client: &mut SpiClient<'_>,
+    relid: Oid,
+    timer_id: Option<i64>,
+) -> Result<(), SpiError> {
+    let owner = client
+        .select(
+            r#"
+            select c.relowner
+            from pg_catalog.pg_class c
+            join pg_catalog.pg_namespace n on n.oid = c.relnamespace
+            where n.nspname = 'quartz' and c.relname = 'spilled_events'
+            "#,
+            None,
+            None,
+        )?
         .first()
-        .get_one::<i64>()?
-        .expect("Timer not found");
+        .get_one::<Oid>()?
+        .expect("commands::spill_timer_event(): quartz.spilled_events does not exist");
+
+    let query = r#"
+        insert into quartz.spilled_events (relid, timer_id)
+        values ($1, $2)
+    "#;
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::OIDOID), relid.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::INT8OID), timer_id.into_datum()),
+    ];
+
+    let mut user_id = pg_sys::InvalidOid;
+    let mut sec_context = 0;
+
+    unsafe {
+        pg_sys::GetUserIdAndSecContext(&mut user_id, &mut sec_context);
+        pg_sys::SetUserIdAndSecContext(
+            owner,
+            sec_context | pg_sys::SECURITY_LOCAL_USERID_CHANGE as i32,
+        );
+    }
+
+    // An error aborts the transaction, which restores the user as well.
+    let result = client.update(query, None, Some(args)).map(|_| ());
 
-    Ok(())
+    unsafe {
+        pg_sys::SetUserIdAndSecContext(user_id, sec_context);
+    }
+
+    result
+}
+
+/// Take up to `limit` spilled events, in the order they were recorded.
+pub fn take_spilled_events(
+    client: &mut SpiClient<'_>,
+    limit: i64,
+) -> Result<Vec<(Oid, Option<i64>)>, SpiError> {
+    let query = r#"
+        with taken as (
+            delete from quartz.spilled_events
+            where id in (
+                select id from quartz.spilled_events
+                order by id
+                limit $1
+            )
+            returning id, relid, timer_id
+        )
+        select relid, timer_id from taken
+        order by id
+    "#;
+
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::INT8OID), limit.into_datum())];
+
+    let tuples = client.update(query, None, Some(args))?;
+
+    let mut vec = Vec::with_capacity(tuples.len());
+
+    for tuple in tuples {
+        let relid = tuple
+            .get::<Oid>(1)
+            .expect("commands::take_spilled_events(): no relid")
+            .expect("commands::take_spilled_events(): relid is null");
+        let timer_id = tuple
+            .get::<i64>(2)
+            .expect("commands::take_spilled_events(): no timer_id");
+
+        vec.push((relid, timer_id));
+    }
+
+    Ok(vec)
 }
@@ -1,10 +1,206 @@
 // src/config.rs
 
 //! Configuration for the quartz extension.
+//!
+//! The configuration is exposed as `quartz.*` settings, which are registered
+//! when the extension is loaded via `shared_preload_libraries`. They can only
+//! be set at server start.
 
-// fixme: move more stuff into this configuration
+use pgrx::prelude::*;
+use pgrx::GucContext;
+use pgrx::GucFlags;
+use pgrx::GucRegistry;
+use pgrx::GucSetting;
+use pgrx::PostgresGucEnum;
+
+use std::time::Duration as StdDuration;
+
+/// The capacity of the shared event queues, which bounds the configurable
+/// queue sizes. It is fixed at compile time, since the queues live in shared
+/// memory.
+pub const MAX_QUEUE_SIZE: usize = 1024;
+
+/// The maximum number of databases that quartz can run in at the same time,
+/// which is fixed at compile time for the same reason.
+pub const MAX_DATABASES: usize = 8;
+
+/// The maximum number of workers per database, which is fixed at compile
+/// time for the same reason.
+pub const MAX_WORKERS: usize = 1024;
+
+/// The database that the launcher connects to for discovering databases.
+pub static DATABASE: GucSetting<Option<&'static str>> = GucSetting::new(Some("postgres"));
 
-/// The database name that will be used for connecting to SPI.
-pub const SPI_DATABASE_NAME: Option<&'static str> = Some("quartz");
 /// The user name that will be used for connecting to SPI.
-pub const SPI_USER_NAME: Option<&'static str> = None;
+pub static ROLE: GucSetting<Option<&'static str>> = GucSetting::new(None);
+
+/// The number of workers firing timers per database, or 0 for half the
+/// available parallelism.
+pub static WORKER_COUNT: GucSetting<i32> = GucSetting::new(0);
+
+/// The number of events the timer subsystem queue can hold.
+pub static TIMER_QUEUE_SIZE: GucSetting<i32> = GucSetting::new(128);
+
+/// The number of events the workers subsystem queue can hold.
+pub static WORKER_QUEUE_SIZE: GucSetting<i32> = GucSetting::new(128);
+
+/// The number of seconds after which crashed background workers are
+/// restarted, or -1 to never restart them.
+pub static RESTART_INTERVAL: GucSetting<i32> = GucSetting::new(1);
+
+/// The level at which timer activity is logged.
+pub static LOG_LEVEL: GucSetting<LogLevel> = GucSetting::new(LogLevel::log);
+
+/// The levels at which timer activity can be logged.
+///
+/// The variants are named after the corresponding Postgres log levels, since
+/// the names are also the values of the setting.
+#[allow(non_camel_case_types)]
+#[derive(PostgresGucEnum, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    debug5,
+    debug4,
+    debug3,
+    debug2,
+    debug1,
+    log,
+    info,
+    notice,
+    warning,
+}
+
+impl From<LogLevel> for PgLogLevel {
+    fn from(value: LogLevel) -> Self {
+        match value {
+            LogLevel::debug5 => PgLogLevel::DEBUG5,
+            LogLevel::debug4 => PgLogLevel::DEBUG4,
+            LogLevel::debug3 => PgLogLevel::DEBUG3,
+            LogLevel::debug2 => PgLogLevel::DEBUG2,
+            LogLevel::debug1 => PgLogLevel::DEBUG1,
+            LogLevel::log => PgLogLevel::LOG,
+            LogLevel::info => PgLogLevel::INFO,
+            LogLevel::notice => PgLogLevel::NOTICE,
+            LogLevel::warning => PgLogLevel::WARNING,
+        }
+    }
+}
+
+/// Register the configuration settings.
+pub(crate) fn pg_init() {
+    GucRegistry::define_string_guc(
+        "quartz.database",
+        "The database that the quartz launcher connects to.",
+        "The launcher discovers the databases that quartz is installed in from this database.",
+        &DATABASE,
+        GucContext::Postmaster,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "quartz.role",
+        "The role that quartz connects as.",
+        "Timers are fired and callbacks are invoked as this role. Defaults to the bootstrap superuser.",
+        &ROLE,
+        GucContext::Postmaster,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "quartz.worker_count",
+        "The number of background workers firing timers per database.",
+        "Set to 0 to use half the available parallelism.",
+        &WORKER_COUNT,
+        0,
+        MAX_WORKERS as i32,
+        GucContext::Postmaster,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "quartz.timer_queue_size",
+        "The number of events the timer subsystem queue can hold.",
+        "Timer changes are written to the quartz.spilled_events table while the queue is full.",
+        &TIMER_QUEUE_SIZE,
+        1,
+        MAX_QUEUE_SIZE as i32,
+        GucContext::Postmaster,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "quartz.worker_queue_size",
+        "The number of fired timers the workers queue can hold.",
+        "Fired timers are retried later while the queue is full.",
+        &WORKER_QUEUE_SIZE,
+        1,
+        MAX_QUEUE_SIZE as i32,
+        GucContext::Postmaster,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "quartz.restart_interval",
+        "The delay before restarting crashed quartz background workers.",
+        "Set to -1 to never restart them.",
+        &RESTART_INTERVAL,
+        -1,
+        3600,
+        GucContext::Postmaster,
+        GucFlags::UNIT_S,
+    );
+
+    GucRegistry::define_enum_guc(
+        "quartz.log_level",
+        "The level at which timer activity is logged.",
+        "",
+        &LOG_LEVEL,
+        GucContext::Postmaster,
+        GucFlags::default(),
+    );
+}
+
+/// The database that the launcher connects to.
+pub fn database() -> Option<String> {
+    DATABASE.get()
+}
+
+/// The role that will be used for connecting to SPI.
+pub fn role() -> Option<String> {
+    ROLE.get()
+}
+
+/// The number of workers firing timers.
+pub fn worker_count() -> usize {
+    match WORKER_COUNT.get() {
+        0 => match std::thread::available_parallelism() {
+            Ok(value) => (value.get() / 2).clamp(1, MAX_WORKERS),
+            Err(e) => {
+                warning!("quartz: failed to determine available parallelism (error: {}), using 1 worker", e);
+
+                1
+            }
+        },
+        value => value as usize,
+    }
+}
+
+/// The restart time of background workers.
+pub fn restart_time() -> Option<StdDuration> {
+    match RESTART_INTERVAL.get() {
+        -1 => None,
+        value => Some(StdDuration::from_secs(value as u64)),
+    }
+}
+
+/// Log timer activity at the configured level.
+macro_rules! log_activity {
+    ($($arg:tt)*) => {
+        pgrx::ereport!(
+            pgrx::PgLogLevel::from(crate::config::LOG_LEVEL.get()),
+            pgrx::PgSqlErrorCode::ERRCODE_SUCCESSFUL_COMPLETION,
+            format!($($arg)*).as_str()
+        )
+    };
+}
+
+pub(crate) use log_activity;
@@ -0,0 +1,446 @@
+// src/cron.rs
+
+//! Cron expressions for timers that are scheduled on a calendar.
+
+use chrono::prelude::*;
+use chrono::LocalResult;
+
+use std::error::Error;
+
+// The number of years to look ahead for the next occurrence of a schedule.
+// This covers the longest gap between two leap days, so that a schedule only
+// yields no occurrence if it can never match (e.g. February 30th).
+const MAX_LOOKAHEAD_YEARS: i32 = 8;
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A parsed cron expression.
+///
+/// The expression consists of the five usual fields: minute, hour, day of
+/// month, month and day of week. Each field is either `*`, a value, a range
+/// `a-b`, or a comma separated list of those, optionally followed by a step
+/// `/n`. Months and days of week may also be given by their three letter
+/// english names. The shorthands `@yearly`, `@monthly`, `@weekly`, `@daily`
+/// and `@hourly` are supported as well.
+///
+/// Schedules are evaluated in the time zone of the timestamps they are given.
+/// For timers, that is the time zone of the server process, as set by its
+/// `TZ` environment variable or the system configuration. The `TimeZone`
+/// setting of the database or session does not apply.
+///
+/// Local times that are skipped by a daylight saving transition occur at the
+/// first valid instant after it, as in Vixie cron and pg_cron. Local times
+/// that are repeated by one occur once, at their earlier instance, or at the
+/// later one if the schedule is evaluated in between.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CronSchedule {
+    // Bit set of the matching minutes, 0-59.
+    minutes: u64,
+
+    // Bit set of the matching hours, 0-23.
+    hours: u32,
+
+    // Bit set of the matching days of month, 1-31.
+    days: u32,
+
+    // Bit set of the matching months, 1-12.
+    months: u16,
+
+    // Bit set of the matching days of week, 0-6 with sunday being 0.
+    weekdays: u8,
+
+    // Whether the day of month and day of week fields were restricted. As in
+    // traditional cron, a day matches if either field matches when both are
+    // restricted.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    /// Parse a cron expression.
+    pub fn parse(expression: &str) -> Result<Self, Box<dyn Error>> {
+        let expression = match expression.trim().to_ascii_lowercase().as_str() {
+            "@yearly" | "@annually" => "0 0 1 1 *".to_string(),
+            "@monthly" => "0 0 1 * *".to_string(),
+            "@weekly" => "0 0 * * 0".to_string(),
+            "@daily" | "@midnight" => "0 0 * * *".to_string(),
+            "@hourly" => "0 * * * *".to_string(),
+            other => other.to_string(),
+        };
+
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+
+        if fields.len() != 5 {
+            return Err(format!(
+                "cron expression must have 5 fields, got {}",
+                fields.len()
+            )
+            .into());
+        }
+
+        let minutes = parse_field(fields[0], 0, 59, &[])?;
+        let hours = parse_field(fields[1], 0, 23, &[])?;
+        let days = parse_field(fields[2], 1, 31, &[])?;
+        let months = parse_field(fields[3], 1, 12, &MONTH_NAMES)?;
+        let weekdays = parse_field(fields[4], 0, 7, &WEEKDAY_NAMES)?;
+
+        // Both 0 and 7 denote sunday.
+        let weekdays = (weekdays | weekdays >> 7) & 0x7f;
+
+        Ok(Self {
+            minutes,
+            hours: hours as u32,
+            days: days as u32,
+            months: months as u16,
+            weekdays: weekdays as u8,
+            days_restricted: !fields[2].starts_with('*'),
+            weekdays_restricted: !fields[4].starts_with('*'),
+        })
+    }
+
+    /// Compute the first occurrence of the schedule strictly after `after`.
+    ///
+    /// Returns None if the schedule has no occurrence in the foreseeable
+    /// future.
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let start = after
+            .naive_local()
+            .with_second(0)?
+            .with_nanosecond(0)?
+            + chrono::Duration::minutes(1);
+
+        let last_year = start.year() + MAX_LOOKAHEAD_YEARS;
+
+        let mut date = start.date();
+        let mut hour = start.hour();
+        let mut minute = start.minute();
+
+        while date.year() <= last_year {
+            if !self.matches_month(date) {
+                date = first_day_of_next_month(date)?;
+                hour = 0;
+                minute = 0;
+                continue;
+            }
+
+            if !self.matches_day(date) {
+                date = date.succ_opt()?;
+                hour = 0;
+                minute = 0;
+                continue;
+            }
+
+            let next_hour = match next_bit(self.hours as u64, hour, 24) {
+                Some(value) => value,
+                None => {
+                    date = date.succ_opt()?;
+                    hour = 0;
+                    minute = 0;
+                    continue;
+                }
+            };
+
+            if next_hour != hour {
+                hour = next_hour;
+                minute = 0;
+            }
+
+            let next_minute = match next_bit(self.minutes, minute, 60) {
+                Some(value) => value,
+                None => {
+                    hour += 1;
+                    minute = 0;
+                    continue;
+                }
+            };
+
+            match after.timezone().from_local_datetime(&date.and_hms_opt(hour, next_minute, 0)?) {
+                LocalResult::Single(value) => return Some(value),
+                // The local time is repeated by a daylight saving transition.
+                // The later instance is only used if `after` lies between
+                // them.
+                LocalResult::Ambiguous(earliest, _) if earliest > *after => return Some(earliest),
+                LocalResult::Ambiguous(_, latest) if latest > *after => return Some(latest),
+                LocalResult::Ambiguous(..) => minute = next_minute + 1,
+                // The local time is skipped by a daylight saving transition,
+                // so the schedule occurs once the transition is over.
+                LocalResult::None => {
+                    return end_of_gap(&after.timezone(), date.and_hms_opt(hour, next_minute, 0)?)
+                }
+            }
+        }
+
+        None
+    }
+
+    fn matches_month(&self, date: NaiveDate) -> bool {
+        self.months & (1 << date.month()) != 0
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+
+        if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+}
+
+// Parse a single field of a cron expression into a bit set of the values it
+// matches. Names, if any, map to consecutive values starting at `min`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, Box<dyn Error>> {
+    let mut bits = 0u64;
+
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(value) if value > 0 => (range, Some(value)),
+                _ => return Err(format!("invalid step in cron field \"{}\"", field).into()),
+            },
+            None => (item, None),
+        };
+
+        let (first, last) = if range == "*" {
+            (min, max)
+        } else if let Some((first, last)) = range.split_once('-') {
+            let first = parse_value(first, min, max, names)?;
+            let last = parse_value(last, min, max, names)?;
+            // Sunday is 0 by name, but ends a range of weekdays as 7, e.g.
+            // "mon-sun".
+            (first, if max == 7 && last == 0 && first > 0 { 7 } else { last })
+        } else {
+            let value = parse_value(range, min, max, names)?;
+            // A single value with a step, e.g. "5/15", extends to the maximum.
+            (value, if step.is_some() { max } else { value })
+        };
+
+        if first > last {
+            return Err(format!("invalid range in cron field \"{}\"", field).into());
+        }
+
+        for value in (first..=last).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+// Parse a single value of a cron field, either numeric or by name.
+fn parse_value(value: &str, min: u32, max: u32, names: &[&str]) -> Result<u32, Box<dyn Error>> {
+    if let Some(index) = names.iter().position(|name| *name == value) {
+        return Ok(min + index as u32);
+    }
+
+    match value.parse::<u32>() {
+        Ok(value) if (min..=max).contains(&value) => Ok(value),
+        Ok(value) => Err(format!("cron value {} is out of range {}-{}", value, min, max).into()),
+        Err(_) => Err(format!("invalid cron value \"{}\"", value).into()),
+    }
+}
+
+// Find the lowest set bit in `from..to`.
+fn next_bit(bits: u64, from: u32, to: u32) -> Option<u32> {
+    (from..to).find(|bit| bits & (1 << bit) != 0)
+}
+
+// Find the first valid local time after a daylight saving transition that
+// skips `local`, at minute granularity.
+fn end_of_gap<Tz: TimeZone>(timezone: &Tz, local: NaiveDateTime) -> Option<DateTime<Tz>> {
+    // Transitions skip at most a few hours.
+    (1..=24 * 60).find_map(|minutes| {
+        timezone
+            .from_local_datetime(&(local + chrono::Duration::minutes(minutes)))
+            .earliest()
+    })
+}
+
+fn first_day_of_next_month(date: NaiveDate) -> Option<NaiveDate> {
+    if date.month() == 12 {
+        NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(date.year(), date.month() + 1, 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::tests::Cet2023;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn cet(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Cet2023> {
+        Cet2023.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn parse(expression: &str) -> CronSchedule {
+        CronSchedule::parse(expression).unwrap()
+    }
+
+    fn next(expression: &str, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        parse(expression).next_after(&after)
+    }
+
+    #[test]
+    fn test_parse_steps() {
+        assert_eq!(parse("*/15 * * * *").minutes, 1 << 0 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(parse("5/20 * * * *").minutes, 1 << 5 | 1 << 25 | 1 << 45);
+        assert_eq!(parse("10-30/10 * * * *").minutes, 1 << 10 | 1 << 20 | 1 << 30);
+        assert_eq!(parse("0 */12 * * *").hours, 1 << 0 | 1 << 12);
+    }
+
+    #[test]
+    fn test_parse_ranges_and_lists() {
+        assert_eq!(parse("0 9-11 * * *").hours, 1 << 9 | 1 << 10 | 1 << 11);
+        assert_eq!(parse("0,30 * * * *").minutes, 1 << 0 | 1 << 30);
+        assert_eq!(parse("0 1-2,20 * * *").hours, 1 << 1 | 1 << 2 | 1 << 20);
+        assert_eq!(parse("0 0 1,15 * *").days, 1 << 1 | 1 << 15);
+    }
+
+    #[test]
+    fn test_parse_names() {
+        assert_eq!(parse("0 0 1 jan,jul *"), parse("0 0 1 1,7 *"));
+        assert_eq!(parse("0 0 1 MAR-may *"), parse("0 0 1 3-5 *"));
+        assert_eq!(parse("0 0 * * mon-fri"), parse("0 0 * * 1-5"));
+        assert_eq!(parse("0 0 * * sun,sat"), parse("0 0 * * 0,6"));
+
+        // Both 0 and 7 denote sunday.
+        assert_eq!(parse("0 0 * * 7"), parse("0 0 * * 0"));
+        assert_eq!(parse("0 0 * * mon-sun"), parse("0 0 * * 1-7"));
+        assert_eq!(parse("0 0 * * fri-sun"), parse("0 0 * * 0,5,6"));
+        assert_eq!(parse("0 0 * * sun-sat"), parse("0 0 * * 0-6"));
+    }
+
+    #[test]
+    fn test_parse_shorthands() {
+        assert_eq!(parse("@yearly"), parse("0 0 1 1 *"));
+        assert_eq!(parse("@annually"), parse("0 0 1 1 *"));
+        assert_eq!(parse("@monthly"), parse("0 0 1 * *"));
+        assert_eq!(parse("@weekly"), parse("0 0 * * 0"));
+        assert_eq!(parse("@daily"), parse("0 0 * * *"));
+        assert_eq!(parse("@midnight"), parse("0 0 * * *"));
+        assert_eq!(parse("@hourly"), parse("0 * * * *"));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for expression in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "@reboot",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * 32 * *",
+            "* * * 0 *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "*/x * * * *",
+            "30-10 * * * *",
+            "a * * * *",
+            "1,,2 * * * *",
+            "* * * foo *",
+            "* * * mon *",
+            "* * * * jan",
+        ] {
+            assert!(CronSchedule::parse(expression).is_err(), "{:?} should be invalid", expression);
+        }
+    }
+
+    #[test]
+    fn test_next_after() {
+        // Occurrences are strictly after the given time, at whole minutes.
+        assert_eq!(next("30 9 * * *", utc(2023, 6, 1, 9, 30)), Some(utc(2023, 6, 2, 9, 30)));
+        assert_eq!(next("30 9 * * *", utc(2023, 6, 1, 9, 29)), Some(utc(2023, 6, 1, 9, 30)));
+        assert_eq!(
+            next("* * * * *", utc(2023, 6, 1, 9, 29) + chrono::Duration::seconds(30)),
+            Some(utc(2023, 6, 1, 9, 30))
+        );
+
+        // Across the end of a month and a year.
+        assert_eq!(next("0 0 1 * *", utc(2023, 12, 15, 0, 0)), Some(utc(2024, 1, 1, 0, 0)));
+        assert_eq!(next("*/20 * * * *", utc(2023, 12, 31, 23, 50)), Some(utc(2024, 1, 1, 0, 0)));
+
+        // Leap days are up to 8 years apart, and February 30th never occurs.
+        assert_eq!(next("0 0 29 2 *", utc(2023, 3, 1, 0, 0)), Some(utc(2024, 2, 29, 0, 0)));
+        assert_eq!(next("0 0 29 2 *", utc(2096, 3, 1, 0, 0)), Some(utc(2104, 2, 29, 0, 0)));
+        assert_eq!(next("0 0 30 2 *", utc(2023, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_next_after_day_of_month_or_day_of_week() {
+        // 2023-01-01 is a sunday. When both day fields are restricted, a day
+        // matches either of them.
+        let schedule = "0 0 15 * mon";
+        assert_eq!(next(schedule, utc(2023, 1, 1, 0, 0)), Some(utc(2023, 1, 2, 0, 0)));
+        assert_eq!(next(schedule, utc(2023, 1, 9, 0, 0)), Some(utc(2023, 1, 15, 0, 0)));
+        assert_eq!(next(schedule, utc(2023, 1, 15, 0, 0)), Some(utc(2023, 1, 16, 0, 0)));
+
+        // Otherwise, only the restricted field applies.
+        assert_eq!(next("0 0 15 * *", utc(2023, 1, 1, 0, 0)), Some(utc(2023, 1, 15, 0, 0)));
+        assert_eq!(next("0 0 * * mon", utc(2023, 1, 2, 0, 0)), Some(utc(2023, 1, 9, 0, 0)));
+
+        // Fields starting with `*` count as unrestricted, even with a step.
+        assert_eq!(next("0 0 */2 * mon", utc(2023, 1, 1, 0, 0)), Some(utc(2023, 1, 9, 0, 0)));
+    }
+
+    #[test]
+    fn test_next_after_spring_forward() {
+        // Local times from 02:00 to 03:00 are skipped on 2023-03-26, and
+        // occur at 03:00 instead.
+        let after = cet(2023, 3, 25, 3, 0);
+        assert_eq!(parse("30 2 * * *").next_after(&after), Some(cet(2023, 3, 26, 3, 0)));
+        assert_eq!(
+            parse("30 2 * * *").next_after(&cet(2023, 3, 26, 3, 0)),
+            Some(cet(2023, 3, 27, 2, 30))
+        );
+
+        // The skipped local times occur once.
+        let after = cet(2023, 3, 26, 1, 50);
+        assert_eq!(parse("*/15 * * * *").next_after(&after), Some(cet(2023, 3, 26, 3, 0)));
+        assert_eq!(
+            parse("*/15 * * * *").next_after(&after).unwrap().with_timezone(&Utc),
+            utc(2023, 3, 26, 1, 0)
+        );
+        assert_eq!(
+            parse("*/15 * * * *").next_after(&cet(2023, 3, 26, 3, 0)),
+            Some(cet(2023, 3, 26, 3, 15))
+        );
+    }
+
+    #[test]
+    fn test_next_after_fall_back() {
+        // Local times from 02:00 to 03:00 are repeated on 2023-10-29, first in
+        // summer time (00:00 to 01:00 UTC), then in standard time (01:00 to
+        // 02:00 UTC). They occur once, at their earlier instance.
+        let schedule = parse("30 2 * * *");
+
+        let first = schedule.next_after(&cet(2023, 10, 29, 0, 0)).unwrap();
+        assert_eq!(first.with_timezone(&Utc), utc(2023, 10, 29, 0, 30));
+
+        let second = schedule.next_after(&first).unwrap();
+        assert_eq!(second.with_timezone(&Utc), utc(2023, 10, 30, 1, 30));
+
+        // Between the two instances, the later one is next.
+        let between = utc(2023, 10, 29, 1, 10).with_timezone(&Cet2023);
+        let next = schedule.next_after(&between).unwrap();
+        assert_eq!(next.with_timezone(&Utc), utc(2023, 10, 29, 1, 30));
+
+        // Schedules within the repeated hour continue after it.
+        let after = utc(2023, 10, 29, 0, 50).with_timezone(&Cet2023);
+        let next = parse("*/15 * * * *").next_after(&after).unwrap();
+        assert_eq!(next.with_timezone(&Utc), utc(2023, 10, 29, 2, 0));
+    }
+}
@@ -0,0 +1,188 @@
+// src/deadlines.rs
+
+//! The deadlines of pending timers.
+//!
+//! Deadlines are kept in a binary min-heap, so that the earliest one is found
+//! in constant time and new ones are inserted in O(log n). Removed or replaced
+//! deadlines are not taken out of the heap right away, but skipped once they
+//! reach its top, which makes removing them O(1).
+
+use chrono::prelude::*;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// The number of stale heap entries that are always tolerated before the heap
+// is rebuilt, so that small heaps are not rebuilt all the time.
+const MIN_STALE_ENTRIES: usize = 1024;
+
+/// The deadlines of a set of keys, ordered by time.
+pub struct Deadlines<K> {
+    // The heap of deadlines. It may contain stale entries, whose key has been
+    // removed or has been given another deadline since.
+    heap: BinaryHeap<Reverse<(DateTime<Local>, K)>>,
+
+    // The current deadline of every key.
+    deadlines: HashMap<K, DateTime<Local>>,
+}
+
+impl<K: Copy + Ord + Hash> Deadlines<K> {
+    /// Set the deadline of a key, replacing its previous one.
+    pub fn insert(&mut self, key: K, deadline: DateTime<Local>) {
+        if self.deadlines.insert(key, deadline) == Some(deadline) {
+            return;
+        }
+
+        self.heap.push(Reverse((deadline, key)));
+        self.compact();
+    }
+
+    /// Remove the deadline of a key.
+    pub fn remove(&mut self, key: &K) -> Option<DateTime<Local>> {
+        let deadline = self.deadlines.remove(key)?;
+        self.compact();
+
+        Some(deadline)
+    }
+
+    /// The earliest deadline, if there is any.
+    pub fn next(&mut self) -> Option<DateTime<Local>> {
+        while let Some(Reverse((deadline, key))) = self.heap.peek() {
+            if self.deadlines.get(key) == Some(deadline) {
+                return Some(*deadline);
+            }
+
+            self.heap.pop();
+        }
+
+        None
+    }
+
+    /// Take the key with the earliest deadline, if that deadline is due at
+    /// `now`.
+    pub fn pop_due(&mut self, now: DateTime<Local>) -> Option<(K, DateTime<Local>)> {
+        if !matches!(self.next(), Some(deadline) if deadline <= now) {
+            return None;
+        }
+
+        let Reverse((deadline, key)) = self.heap.pop()?;
+        self.deadlines.remove(&key);
+
+        Some((key, deadline))
+    }
+
+    /// The deadlines of all keys, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &DateTime<Local>)> {
+        self.deadlines.iter()
+    }
+
+    // Rebuild the heap once most of its entries are stale, which bounds its
+    // size to a small multiple of the number of deadlines.
+    fn compact(&mut self) {
+        if self.heap.len() > 2 * self.deadlines.len() + MIN_STALE_ENTRIES {
+            self.heap = self
+                .deadlines
+                .iter()
+                .map(|(key, deadline)| Reverse((*deadline, *key)))
+                .collect();
+        }
+    }
+}
+
+impl<K: Copy + Ord + Hash> Default for Deadlines<K> {
+    fn default() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            deadlines: HashMap::new(),
+        }
+    }
+}
+
+// The benchmarks include this file as well, so its tests do not depend on the
+// pgrx test framework.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(second: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2023, 6, 1, 9, 0, second).unwrap()
+    }
+
+    #[test]
+    fn test_next() {
+        let mut deadlines = Deadlines::default();
+        assert_eq!(deadlines.next(), None);
+
+        deadlines.insert(1, at(30));
+        deadlines.insert(2, at(10));
+        deadlines.insert(3, at(20));
+
+        assert_eq!(deadlines.next(), Some(at(10)));
+    }
+
+    #[test]
+    fn test_insert_replaces_deadline() {
+        let mut deadlines = Deadlines::default();
+
+        deadlines.insert(1, at(10));
+        deadlines.insert(2, at(20));
+        deadlines.insert(1, at(30));
+
+        assert_eq!(deadlines.next(), Some(at(20)));
+        assert_eq!(deadlines.iter().count(), 2);
+
+        deadlines.insert(2, at(40));
+        assert_eq!(deadlines.next(), Some(at(30)));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut deadlines = Deadlines::default();
+
+        deadlines.insert(1, at(10));
+        deadlines.insert(2, at(20));
+
+        assert_eq!(deadlines.remove(&1), Some(at(10)));
+        assert_eq!(deadlines.remove(&1), None);
+        assert_eq!(deadlines.next(), Some(at(20)));
+
+        assert_eq!(deadlines.remove(&2), Some(at(20)));
+        assert_eq!(deadlines.next(), None);
+    }
+
+    #[test]
+    fn test_pop_due() {
+        let mut deadlines = Deadlines::default();
+
+        deadlines.insert(1, at(20));
+        deadlines.insert(2, at(10));
+        deadlines.insert(3, at(30));
+        deadlines.insert(3, at(15));
+
+        assert_eq!(deadlines.pop_due(at(5)), None);
+        assert_eq!(deadlines.pop_due(at(20)), Some((2, at(10))));
+        assert_eq!(deadlines.pop_due(at(20)), Some((3, at(15))));
+        assert_eq!(deadlines.pop_due(at(20)), Some((1, at(20))));
+        assert_eq!(deadlines.pop_due(at(59)), None);
+        assert_eq!(deadlines.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut deadlines = Deadlines::default();
+
+        // Every replaced deadline leaves a stale entry behind in the heap.
+        for i in 0..10 * MIN_STALE_ENTRIES as u32 {
+            deadlines.insert(1, at(i % 60));
+        }
+
+        deadlines.insert(2, at(0));
+
+        assert!(deadlines.heap.len() <= 2 * 2 + MIN_STALE_ENTRIES);
+        assert_eq!(deadlines.next(), Some(at(0)));
+        assert_eq!(deadlines.pop_due(at(0)), Some((2, at(0))));
+        assert_eq!(deadlines.next(), Some(at((10 * MIN_STALE_ENTRIES as u32 - 1) % 60)));
+    }
+}
@@ -1,15 +1,28 @@
 // src/functions.rs
 
+use chrono::prelude::*;
 use pgrx::prelude::*;
+use pgrx::JsonB;
+use pgrx::spi;
 use pgrx::spi::Error as SpiError;
 use pgrx::spi::SpiClient;
 
+use crate::commands;
+use crate::commands::TimerTableData;
+use crate::launcher::DatabaseSlots;
+use crate::stats;
 use crate::timer::TimerHandle;
 use crate::timer::TimerSubsystemEvent;
+use crate::timestamp;
+use crate::types::ColumnMapping;
+use crate::types::MisfireAction;
+use crate::types::MisfirePolicy;
+use crate::types::TimerId;
+use crate::workers::WorkersHandle;
 
-pub fn create_timers_table(rel: &str) {
+pub fn create_timers_table(rel: &str, payload: bool) {
     if let Err(e) =
-        Spi::connect(|mut client| self::create_timers_table_with_client(&mut client, rel))
+        Spi::connect(|mut client| self::create_timers_table_with_client(&mut client, rel, payload))
     {
         error!("quartz.create_timers_table(): {}", e);
     }
@@ -18,20 +31,25 @@ pub fn create_timers_table(rel: &str) {
 fn create_timers_table_with_client<'a>(
     client: &mut SpiClient<'a>,
     rel: &str,
+    payload: bool,
 ) -> Result<(), SpiError> {
-    let (schema_str, table) = rel
-        .split_once(".")
-        .map(|(schema, table)| (Some(schema), table))
-        .unwrap_or((None, rel));
-
-    let fq = if let Some(schema) = schema_str {
-        format!("\"{}\".\"{}\"", schema, table)
-    } else {
-        format!("\"{}\"", table)
+    // The table does not exist yet, so its name is parsed the way Postgres
+    // parses it, and its parts are quoted again.
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::TEXTOID), rel.into_datum())];
+
+    let names = client
+        .select("select parse_ident($1)", None, Some(args))?
+        .first()
+        .get_one::<Vec<String>>()?
+        .unwrap_or_default();
+
+    let name = match names.as_slice() {
+        [table] => spi::quote_identifier(table),
+        [schema, table] => spi::quote_qualified_identifier(schema, table),
+        _ => error!("quartz.create_timers_table(): invalid relation name {}", rel),
     };
 
-    let schema_arg = schema_str.map(|s| format!("'{}'", s)).unwrap_or_else(|| "current_schema()".to_string());
-    let table_arg = format!("'{}'", table);
+    let payload_column = if payload { "payload jsonb," } else { "" };
 
     let query = format!(
         r#"
@@ -39,52 +57,783 @@ fn create_timers_table_with_client<'a>(
                 id bigint generated always as identity primary key,
                 expires_at timestamp with time zone not null,
                 fired_at timestamp with time zone,
-                completed_at timestamp with time zone
-            );
-
-            with table_oid as (
-                select c.oid
-                from pg_catalog.pg_class c
-                join pg_catalog.pg_namespace n on n.oid = c.relnamespace
-                where c.relname = {}
-                and n.nspname = {}
+                completed_at timestamp with time zone,
+                attempts integer not null default 0,
+                last_error text,
+                repeat_every interval check (repeat_every > interval '0'),
+                schedule text check (quartz.is_valid_cron(schedule)),
+                repeat_until timestamp with time zone,
+                max_occurrences integer check (max_occurrences > 0),
+                occurrences integer not null default 0,
+                missed_at timestamp with time zone,
+                {}
+                check (repeat_every is null or schedule is null)
             )
-            insert into quartz.timer_relations (relid)
-            select oid from table_oid
-            returning relid;
         "#,
-        fq, table_arg, schema_arg
+        name, payload_column
     );
 
-    let result = client.update(query.as_str(), None, None)?.first();
+    client.update(query.as_str(), None, None)?;
 
-    let table_oid = match result.get_one() {
-        Ok(Some(value)) => value,
-        Ok(None) => {
-            error!("quartz.create_timers_table(): failed to get table OID: no result");
-        }
-        Err(e) => {
-            error!("quartz.create_timers_table(): failed to get table OID: {}", e);
-        }
+    let table_oid = self::resolve_relation(client, name.as_str())?;
+
+    let columns = ColumnMapping {
+        payload: payload.then(|| "payload".to_string()),
+        ..ColumnMapping::default()
+    };
+
+    self::insert_timer_relation(client, table_oid, &columns)?;
+
+    let timer_table = match commands::find_timer_table(client, table_oid)? {
+        Some(value) => value,
+        None => error!("quartz.create_timers_table(): {} is not a timers table", rel),
     };
 
-    if let Err(e) = self::activate_timers_with_client(client, rel) {
+    if let Err(e) = self::create_triggers_with_client(client, &timer_table) {
         error!("quartz.create_timers_table(): failed to activate timers: {}", e);
     }
 
-    if !TimerHandle::get().enqueue_event(TimerSubsystemEvent::TrackTimersTable { table_oid }) {
+    let event = TimerSubsystemEvent::TrackTimersTable {
+        table_oid,
+        misfire_policy: timer_table.misfire_policy,
+    };
+
+    if !TimerHandle::get().enqueue_event_on_commit(event) {
         error!("quartz.create_timers_table(): failed to enqueue event");
     }
 
     Ok(())
 }
 
-pub fn drop_timers_table(rel: &str) {
-    error!("quartz.drop_timers_table(): not implemented");
+pub fn register_timers_table(rel: &str, mapping: JsonB, add_columns: bool) {
+    if let Err(e) = Spi::connect(|mut client| {
+        self::register_timers_table_with_client(&mut client, rel, Some(mapping), add_columns)
+    }) {
+        error!("quartz.register_timers_table(): {}", e);
+    }
+}
+
+/// Register a table as a timers table. Without a mapping, the columns of the
+/// table are named after their roles.
+///
+/// Columns that quartz manages itself are only added to the table if
+/// `add_columns` is set, otherwise optional roles without a column are left
+/// unmapped.
+fn register_timers_table_with_client<'a>(
+    client: &mut SpiClient<'a>,
+    rel: &str,
+    mapping: Option<JsonB>,
+    add_columns: bool,
+) -> Result<(), SpiError> {
+    let object = mapping.as_ref().map(|mapping| match mapping.0.as_object() {
+        Some(value) => value,
+        None => error!("quartz.register_timers_table(): mapping must be a JSON object"),
+    });
+
+    for (role, column) in object.into_iter().flatten() {
+        if !ColumnMapping::ROLES.contains(&role.as_str()) {
+            error!("quartz.register_timers_table(): unknown column role \"{}\"", role);
+        }
+
+        if column.is_null() && ColumnMapping::REQUIRED_ROLES.contains(&role.as_str()) {
+            error!("quartz.register_timers_table(): role \"{}\" must be mapped onto a column", role);
+        }
+
+        if !column.is_string() && !column.is_null() {
+            error!("quartz.register_timers_table(): column of role \"{}\" must be a string or null", role);
+        }
+    }
+
+    // Roles that the mapping leaves out are looked for in columns named after
+    // them, while roles mapped onto null have no column.
+    let candidates = ColumnMapping::ROLES
+        .iter()
+        .map(|role| match object.and_then(|object| object.get(*role)) {
+            Some(column) => column.as_str(),
+            None => Some(*role),
+        })
+        .collect::<Vec<_>>();
+
+    for (i, column) in candidates.iter().enumerate() {
+        if column.is_some() && candidates[..i].contains(column) {
+            error!(
+                "quartz.register_timers_table(): column \"{}\" is mapped onto more than one role",
+                column.unwrap_or_default()
+            );
+        }
+    }
+
+    let table_oid = self::resolve_relation(client, rel)?;
+    let name = self::relation_name(client, table_oid)?;
+
+    let mut columns = Vec::with_capacity(candidates.len());
+
+    for (role, column) in ColumnMapping::ROLES.iter().zip(candidates) {
+        let column = match column {
+            Some(value) => value,
+            None => {
+                columns.push(None);
+                continue;
+            }
+        };
+
+        let (column_type, definition) = self::column_role_definition(role);
+        let mapped = object.is_some_and(|object| object.contains_key(*role));
+        let required = ColumnMapping::REQUIRED_ROLES.contains(role);
+
+        match (self::column_type(client, table_oid, column)?, definition) {
+            (Some(existing_type), _) if existing_type == column_type => {}
+            (Some(existing_type), _) => error!(
+                "quartz.register_timers_table(): column \"{}\" of role \"{}\" must be {}, not {}",
+                column, role, column_type, existing_type
+            ),
+            // Columns that quartz manages itself are only added if the caller
+            // asks for it.
+            (None, Some(definition)) if !mapped && add_columns => {
+                let query = format!(
+                    "alter table {} add column {} {}",
+                    name,
+                    spi::quote_identifier(column),
+                    definition
+                );
+
+                client.update(query.as_str(), None, None)?;
+            }
+            (None, _) if !mapped && !required => {
+                columns.push(None);
+                continue;
+            }
+            (None, _) => error!(
+                "quartz.register_timers_table(): column \"{}\" of role \"{}\" does not exist",
+                column, role
+            ),
+        }
+
+        columns.push(Some(column));
+    }
+
+    let columns = match ColumnMapping::from_columns(columns) {
+        Ok(value) => value,
+        Err(e) => error!("quartz.register_timers_table(): {}", e),
+    };
+
+    // Timers are looked up by their ID, which must therefore be unique.
+    let query = r#"
+        select exists (
+            select 1
+            from pg_catalog.pg_index i
+            join pg_catalog.pg_attribute a on a.attrelid = i.indrelid and a.attnum = i.indkey[0]
+            where i.indrelid = $1
+            and i.indisunique
+            and i.indnatts = 1
+            and i.indpred is null
+            and a.attname = $2
+        )
+    "#;
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::OIDOID), table_oid.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), columns.id.as_str().into_datum()),
+    ];
+
+    if client.select(query, None, Some(args))?.first().get_one::<bool>()? != Some(true) {
+        error!(
+            "quartz.register_timers_table(): column \"{}\" of role \"id\" must be unique",
+            columns.id
+        );
+    }
+
+    let query = format!(
+        "select exists (select 1 from {} where {} is null or {} is null)",
+        name,
+        spi::quote_identifier(&columns.id),
+        spi::quote_identifier(&columns.expires_at)
+    );
+
+    if client.select(query.as_str(), None, None)?.first().get_one::<bool>()? == Some(true) {
+        error!(
+            "quartz.register_timers_table(): columns \"{}\" and \"{}\" must not contain nulls",
+            columns.id, columns.expires_at
+        );
+    }
+
+    self::insert_timer_relation(client, table_oid, &columns)?;
+
+    let timer_table = match commands::find_timer_table(client, table_oid)? {
+        Some(value) => value,
+        None => error!("quartz.register_timers_table(): {} is not a timers table", rel),
+    };
+
+    if let Err(e) = self::create_triggers_with_client(client, &timer_table) {
+        error!("quartz.register_timers_table(): failed to activate timers: {}", e);
+    }
+
+    let event = TimerSubsystemEvent::TrackTimersTable {
+        table_oid,
+        misfire_policy: timer_table.misfire_policy,
+    };
+
+    if !TimerHandle::get().enqueue_event_on_commit(event) {
+        error!("quartz.register_timers_table(): failed to enqueue event");
+    }
+
+    self::arm_pending_timers(client, &timer_table)
+}
+
+/// Record a table as a timers table, along with the columns of its roles.
+fn insert_timer_relation<'a>(
+    client: &mut SpiClient<'a>,
+    table_oid: pg_sys::Oid,
+    columns: &ColumnMapping,
+) -> Result<(), SpiError> {
+    // Roles without a column are recorded with null.
+    let query = r#"
+        insert into quartz.timer_relations (relid, column_mapping)
+        values ($1, jsonb_object($2, $3))
+    "#;
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::OIDOID), table_oid.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::TEXTARRAYOID), ColumnMapping::ROLES.to_vec().into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::TEXTARRAYOID), columns.columns().to_vec().into_datum()),
+    ];
+
+    client.update(query, None, Some(args)).map(|_| ())
+}
+
+/// Arm the pending timers of a table that starts being tracked, like newly
+/// inserted ones.
+fn arm_pending_timers<'a>(
+    client: &SpiClient<'a>,
+    timer_table: &TimerTableData,
+) -> Result<(), SpiError> {
+    let timers = commands::find_timers_in_table(
+        client,
+        timer_table.schema.as_str(),
+        timer_table.table.as_str(),
+        &timer_table.columns,
+    )?;
+
+    for timer in timers {
+        if timer.fired_at.is_some()
+            || timer.missed_at.is_some()
+            || timer.attempts >= timer_table.retry_policy.max_attempts
+        {
+            continue;
+        }
+
+        let event = TimerSubsystemEvent::CreateTimer {
+            table_oid: timer_table.relid,
+            table_row: timer.into(),
+        };
+
+        if !TimerHandle::get().enqueue_event_on_commit(event) {
+            error!("failed to enqueue timer");
+        }
+    }
+
+    Ok(())
+}
+
+pub fn timers_table_dropped(relid: pg_sys::Oid) {
+    let event = TimerSubsystemEvent::UntrackTimersTable { table_oid: relid };
+
+    if !TimerHandle::get().enqueue_event_on_commit(event) {
+        error!("quartz.timers_table_dropped(): failed to enqueue event");
+    }
+}
+
+pub fn check_timers_table(relid: pg_sys::Oid) {
+    if let Err(e) = Spi::connect(|client| self::check_timers_table_with_client(&client, relid)) {
+        error!("quartz.check_timers_table(): {}", e);
+    }
+}
+
+fn check_timers_table_with_client<'a>(
+    client: &SpiClient<'a>,
+    relid: pg_sys::Oid,
+) -> Result<(), SpiError> {
+    let timer_table = match commands::find_timer_table(client, relid)? {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+
+    let name = timer_table.qualified_name();
+
+    // Roles without a column are not checked, they stay without one.
+    for (role, column) in ColumnMapping::ROLES.iter().zip(timer_table.columns.columns()) {
+        let column = match column {
+            Some(value) => value,
+            None => continue,
+        };
+
+        let (column_type, _) = self::column_role_definition(role);
+
+        match self::column_type(client, relid, column)? {
+            Some(existing_type) if existing_type == column_type => {}
+            Some(existing_type) => error!(
+                "{}: column \"{}\" of role \"{}\" must be {}, not {}",
+                name, column, role, column_type, existing_type
+            ),
+            None => error!(
+                "{}: column \"{}\" of role \"{}\" is required by quartz",
+                name, column, role
+            ),
+        }
+    }
+
+    if let Some(key) = &timer_table.misfire_key {
+        if self::column_type(client, relid, key)?.is_none() {
+            error!(
+                "{}: column \"{}\" is the misfire key of the table and is required by quartz",
+                name, key
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The type of a column of a table, if the table has such a column.
+fn column_type<'a>(
+    client: &SpiClient<'a>,
+    relid: pg_sys::Oid,
+    column: &str,
+) -> Result<Option<String>, SpiError> {
+    let query = r#"
+        select format_type(atttypid, atttypmod)
+        from pg_catalog.pg_attribute
+        where attrelid = $1 and attname = $2 and attnum > 0 and not attisdropped
+    "#;
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::OIDOID), relid.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), column.into_datum()),
+    ];
+
+    client.select(query, None, Some(args))?.first().get_one::<String>()
+}
+
+/// The type of the column of a role, and the definition of the column that is
+/// added to registered tables that lack it on request, if quartz manages it
+/// itself.
+fn column_role_definition(role: &str) -> (&'static str, Option<&'static str>) {
+    match role {
+        "id" => ("bigint", None),
+        "expires_at" => ("timestamp with time zone", None),
+        "fired_at" | "completed_at" | "missed_at" | "repeat_until" => {
+            ("timestamp with time zone", Some("timestamp with time zone"))
+        }
+        "attempts" | "occurrences" => ("integer", Some("integer not null default 0")),
+        "last_error" => ("text", Some("text")),
+        "repeat_every" => ("interval", Some("interval")),
+        "schedule" => ("text", Some("text")),
+        "max_occurrences" => ("integer", Some("integer")),
+        "payload" => ("jsonb", None),
+        _ => unreachable!("unknown column role {}", role),
+    }
+}
+
+pub fn drop_timers_table(rel: &str, keep_data: bool) {
+    if let Err(e) =
+        Spi::connect(|mut client| self::drop_timers_table_with_client(&mut client, rel, keep_data))
+    {
+        error!("quartz.drop_timers_table(): {}", e);
+    }
+}
+
+fn drop_timers_table_with_client<'a>(
+    client: &mut SpiClient<'a>,
+    rel: &str,
+    keep_data: bool,
+) -> Result<(), SpiError> {
+    let timer_table = self::find_timer_table_by_name(client, rel)?;
+    let table_oid = timer_table.relid;
+
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::OIDOID), table_oid.into_datum())];
+
+    client.update("delete from quartz.timer_relations where relid = $1", None, Some(args))?;
+
+    if let Err(e) = self::drop_triggers_with_client(client, &timer_table) {
+        error!("quartz.drop_timers_table(): failed to deactivate timers: {}", e);
+    }
+
+    if !keep_data {
+        let query = format!("drop table {}", timer_table.qualified_name());
+
+        client.update(query.as_str(), None, None)?;
+    }
+
+    // Deactivated tables are not tracked anymore.
+    if timer_table.enabled
+        && !TimerHandle::get().enqueue_event_on_commit(TimerSubsystemEvent::UntrackTimersTable { table_oid })
+    {
+        error!("quartz.drop_timers_table(): failed to enqueue event");
+    }
+
+    Ok(())
+}
+
+pub fn set_timer_callback(rel: &str, callback: Option<&str>) {
+    if let Err(e) =
+        Spi::connect(|mut client| self::set_timer_callback_with_client(&mut client, rel, callback))
+    {
+        error!("quartz.set_timer_callback(): {}", e);
+    }
+}
+
+fn set_timer_callback_with_client<'a>(
+    client: &mut SpiClient<'a>,
+    rel: &str,
+    callback: Option<&str>,
+) -> Result<(), SpiError> {
+    // The callback runs as the owner of the table, so only the owner may set
+    // it.
+    let query = r#"
+        select pg_catalog.pg_has_role(c.relowner, 'USAGE')
+        from pg_catalog.pg_class c
+        where c.oid = $1::regclass;
+    "#;
+
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::TEXTOID), rel.into_datum())];
+
+    let is_owner = client
+        .select(query, None, Some(args))?
+        .first()
+        .get_one::<bool>()?
+        .unwrap_or(false);
+
+    if !is_owner {
+        error!("quartz.set_timer_callback(): must be owner of table {}", rel);
+    }
+
+    if let Some(callback) = callback {
+        let query = r#"
+            select p.pronargs = 1 and p.proargtypes[0] = c.reltype
+            from pg_catalog.pg_proc p, pg_catalog.pg_class c
+            where p.oid = $2::regprocedure
+            and c.oid = $1::regclass;
+        "#;
+
+        let args = vec![
+            (PgOid::Custom(pgrx::pg_sys::TEXTOID), rel.into_datum()),
+            (PgOid::Custom(pgrx::pg_sys::TEXTOID), callback.into_datum()),
+        ];
+
+        let accepts_row = client
+            .select(query, None, Some(args))?
+            .first()
+            .get_one::<bool>()?
+            .unwrap_or(false);
+
+        if !accepts_row {
+            error!(
+                "quartz.set_timer_callback(): {} must take a single argument of the row type of {}",
+                callback, rel
+            );
+        }
+    }
+
+    let query = r#"
+        update quartz.timer_relations
+        set callback = $2::regprocedure
+        where relid = $1::regclass
+        returning relid;
+    "#;
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), rel.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), callback.into_datum()),
+    ];
+
+    if client.update(query, None, Some(args))?.is_empty() {
+        error!("quartz.set_timer_callback(): {} is not a timers table", rel);
+    }
+
+    Ok(())
+}
+
+pub fn set_timer_notify_channel(rel: &str, channel: Option<&str>) {
+    if let Err(e) = Spi::connect(|mut client| {
+        self::set_timer_notify_channel_with_client(&mut client, rel, channel)
+    }) {
+        error!("quartz.set_timer_notify_channel(): {}", e);
+    }
+}
+
+fn set_timer_notify_channel_with_client<'a>(
+    client: &mut SpiClient<'a>,
+    rel: &str,
+    channel: Option<&str>,
+) -> Result<(), SpiError> {
+    let query = r#"
+        update quartz.timer_relations
+        set notify_channel = $2
+        where relid = $1::regclass
+        returning relid;
+    "#;
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), rel.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), channel.into_datum()),
+    ];
+
+    if client.update(query, None, Some(args))?.is_empty() {
+        error!("quartz.set_timer_notify_channel(): {} is not a timers table", rel);
+    }
+
+    Ok(())
+}
+
+pub fn set_retry_policy(
+    rel: &str,
+    max_attempts: i32,
+    backoff: Interval,
+    backoff_max: Interval,
+    jitter: f64,
+    redeliver: bool,
+) {
+    if let Err(e) = Spi::connect(|mut client| {
+        self::set_retry_policy_with_client(
+            &mut client,
+            rel,
+            max_attempts,
+            backoff,
+            backoff_max,
+            jitter,
+            redeliver,
+        )
+    }) {
+        error!("quartz.set_retry_policy(): {}", e);
+    }
+}
+
+fn set_retry_policy_with_client<'a>(
+    client: &mut SpiClient<'a>,
+    rel: &str,
+    max_attempts: i32,
+    backoff: Interval,
+    backoff_max: Interval,
+    jitter: f64,
+    redeliver: bool,
+) -> Result<(), SpiError> {
+    let timer_table = self::find_timer_table_by_name(client, rel)?;
+
+    // Failures are counted in the attempts column.
+    if max_attempts > 1 && timer_table.columns.attempts.is_none() {
+        error!(
+            "quartz.set_retry_policy(): retrying timers requires a column of role \"attempts\" in {}",
+            rel
+        );
+    }
+
+    let query = r#"
+        update quartz.timer_relations
+        set
+            max_attempts = $2,
+            retry_backoff = $3,
+            retry_backoff_max = $4,
+            retry_jitter = $5,
+            redeliver = $6
+        where relid = $1::regclass
+        returning relid;
+    "#;
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), rel.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::INT4OID), max_attempts.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::INTERVALOID), backoff.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::INTERVALOID), backoff_max.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::FLOAT8OID), jitter.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::BOOLOID), redeliver.into_datum()),
+    ];
+
+    if client.update(query, None, Some(args))?.is_empty() {
+        error!("quartz.set_retry_policy(): {} is not a timers table", rel);
+    }
+
+    Ok(())
+}
+
+pub fn set_misfire_policy(
+    rel: &str,
+    policy: &str,
+    threshold: Interval,
+    key: Option<&str>,
+    rate: Option<f64>,
+) {
+    if let Err(e) = Spi::connect(|mut client| {
+        self::set_misfire_policy_with_client(&mut client, rel, policy, threshold, key, rate)
+    }) {
+        error!("quartz.set_misfire_policy(): {}", e);
+    }
+}
+
+fn set_misfire_policy_with_client<'a>(
+    client: &mut SpiClient<'a>,
+    rel: &str,
+    policy: &str,
+    threshold: Interval,
+    key: Option<&str>,
+    rate: Option<f64>,
+) -> Result<(), SpiError> {
+    let misfire_policy = match MisfirePolicy::try_new(policy, rate, threshold.into()) {
+        Ok(value) => value,
+        Err(e) => error!("quartz.set_misfire_policy(): {}", e),
+    };
+
+    let TimerTableData { relid, columns, .. } = self::find_timer_table_by_name(client, rel)?;
+
+    // Skipped timers that do not recur are marked as missed.
+    let skips = matches!(misfire_policy.action, MisfireAction::Skip | MisfireAction::FireLatest);
+
+    if skips && columns.missed_at.is_none() {
+        error!(
+            "quartz.set_misfire_policy(): {} requires a column of role \"missed_at\" in {}",
+            policy, rel
+        );
+    }
+
+    if let Some(key) = key {
+        if misfire_policy.action != MisfireAction::FireLatest {
+            error!("quartz.set_misfire_policy(): only fire_latest takes a key");
+        }
+
+        let query = r#"
+            select 1
+            from pg_catalog.pg_attribute
+            where attrelid = $1 and attname = $2 and attnum > 0 and not attisdropped
+        "#;
+
+        let args = vec![
+            (PgOid::Custom(pgrx::pg_sys::OIDOID), relid.into_datum()),
+            (PgOid::Custom(pgrx::pg_sys::TEXTOID), key.into_datum()),
+        ];
+
+        if client.select(query, None, Some(args))?.is_empty() {
+            error!("quartz.set_misfire_policy(): {} has no column \"{}\"", rel, key);
+        }
+    }
+
+    let query = r#"
+        update quartz.timer_relations
+        set
+            misfire_policy = $2,
+            misfire_threshold = $3,
+            misfire_key = $4,
+            misfire_rate = $5
+        where relid = $1
+    "#;
+
+    let args = vec![
+        (PgOid::Custom(pgrx::pg_sys::OIDOID), relid.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), policy.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::INTERVALOID), Interval::from(misfire_policy.threshold).into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::TEXTOID), key.into_datum()),
+        (PgOid::Custom(pgrx::pg_sys::FLOAT8OID), rate.into_datum()),
+    ];
+
+    client.update(query, None, Some(args))?;
+
+    let event = TimerSubsystemEvent::UpdateMisfirePolicy {
+        table_oid: relid,
+        misfire_policy,
+    };
+
+    if !TimerHandle::get().enqueue_event_on_commit(event) {
+        error!("quartz.set_misfire_policy(): failed to enqueue event");
+    }
+
+    Ok(())
+}
+
+pub fn complete_timer(rel: &str, id: i64) {
+    if let Err(e) = Spi::connect(|mut client| self::complete_timers_with_client(&mut client, rel, &[id]))
+    {
+        error!("quartz.complete_timer(): {}", e);
+    }
+}
+
+pub fn complete_timers(rel: &str, ids: Vec<i64>) {
+    if let Err(e) =
+        Spi::connect(|mut client| self::complete_timers_with_client(&mut client, rel, &ids))
+    {
+        error!("quartz.complete_timers(): {}", e);
+    }
+}
+
+fn complete_timers_with_client<'a>(
+    client: &mut SpiClient<'a>,
+    rel: &str,
+    ids: &[i64],
+) -> Result<(), SpiError> {
+    let TimerTableData {
+        relid,
+        schema,
+        table,
+        columns,
+        ..
+    } = self::find_timer_table_by_name(client, rel)?;
+
+    for &id in ids {
+        let timer_id = TimerId::new(relid, id);
+
+        let timer = match commands::find_timer_for_update(client, &schema, &table, &columns, id)? {
+            Some(value) => value,
+            None => error!("complete timer {}: timer does not exist", timer_id),
+        };
+
+        if timer.fired_at.is_none() {
+            error!("complete timer {}: timer has not fired yet", timer_id);
+        }
+
+        if timer.completed_at.is_some() {
+            error!("complete timer {}: timer is already completed", timer_id);
+        }
+
+        commands::mark_timer_as_completed(client, &schema, &table, &columns, id)?;
+    }
+
+    Ok(())
+}
+
+/// Resolve a relation name through regclass, raising an error if there is no
+/// such relation.
+fn resolve_relation<'a>(client: &SpiClient<'a>, rel: &str) -> Result<pg_sys::Oid, SpiError> {
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::TEXTOID), rel.into_datum())];
+
+    let table_oid = client
+        .select("select $1::regclass::oid", None, Some(args))?
+        .first()
+        .get_one::<pg_sys::Oid>()?;
+
+    Ok(table_oid.expect("regclass of an existing relation is not null"))
+}
+
+/// The name of a relation, quoted and schema-qualified where required.
+fn relation_name<'a>(client: &SpiClient<'a>, oid: pg_sys::Oid) -> Result<String, SpiError> {
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::OIDOID), oid.into_datum())];
+
+    let name = client
+        .select("select $1::regclass::text", None, Some(args))?
+        .first()
+        .get_one::<String>()?;
+
+    Ok(name.expect("regclass of an existing relation is not null"))
 }
 
-fn drop_timers_table_with_client<'a>(client: &mut SpiClient<'a>, rel: &str) {
-    todo!("not implemented")
+/// Find the timers table that a relation name refers to, raising an error if
+/// the relation is not a timers table.
+fn find_timer_table_by_name<'a>(
+    client: &SpiClient<'a>,
+    rel: &str,
+) -> Result<TimerTableData, SpiError> {
+    let table_oid = self::resolve_relation(client, rel)?;
+
+    match commands::find_timer_table(client, table_oid)? {
+        Some(value) => Ok(value),
+        None => error!("{} is not a timers table", rel),
+    }
 }
 
 pub fn activate_timers(rel: &str) {
@@ -94,61 +843,318 @@ pub fn activate_timers(rel: &str) {
 }
 
 fn activate_timers_with_client<'a>(client: &mut SpiClient<'a>, rel: &str) -> Result<(), SpiError> {
+    let table_oid = self::resolve_relation(client, rel)?;
+
+    let timer_table = match commands::find_timer_table(client, table_oid)? {
+        Some(value) => value,
+        // Tables that are not timers tables yet are registered, with their
+        // columns named after their roles.
+        None => return self::register_timers_table_with_client(client, rel, None, false),
+    };
+
+    self::create_triggers_with_client(client, &timer_table)?;
+
+    if timer_table.enabled {
+        return Ok(());
+    }
+
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::OIDOID), table_oid.into_datum())];
+
+    client.update(
+        "update quartz.timer_relations set enabled = true where relid = $1",
+        None,
+        Some(args),
+    )?;
+
+    let event = TimerSubsystemEvent::TrackTimersTable {
+        table_oid,
+        misfire_policy: timer_table.misfire_policy,
+    };
+
+    if !TimerHandle::get().enqueue_event_on_commit(event) {
+        error!("quartz.activate_timers(): failed to enqueue event");
+    }
+
+    self::arm_pending_timers(client, &timer_table)
+}
+
+/// Create the triggers that keep the timer subsystem up to date with the rows
+/// of a timers table.
+fn create_triggers_with_client<'a>(
+    client: &mut SpiClient<'a>,
+    timer_table: &TimerTableData,
+) -> Result<(), SpiError> {
+    // The triggers get the column mapping of the table as their arguments,
+    // with empty strings for roles without a column.
+    let args = timer_table
+        .columns
+        .columns()
+        .iter()
+        .map(|column| spi::quote_literal(column.unwrap_or_default()))
+        .collect::<Vec<_>>()
+        .join(", ");
+
     let query = format!(
         r#"
         create or replace trigger quartz_timers_before_insert
-            before insert on {}
+            before insert on {rel}
             for each row
-            execute procedure quartz.quartz_timers_before_insert();
+            execute procedure quartz.quartz_timers_before_insert({args});
         create or replace trigger quartz_timers_after_insert
-            after insert on {}
+            after insert on {rel}
             for each row
-            execute procedure quartz.quartz_timers_after_insert();
+            execute procedure quartz.quartz_timers_after_insert({args});
         create or replace trigger quartz_timers_before_update
-            before update on {}
+            before update on {rel}
             for each row
-            execute procedure quartz.quartz_timers_before_update();
+            execute procedure quartz.quartz_timers_before_update({args});
         create or replace trigger quartz_timers_after_update
-            after update on {}
+            after update on {rel}
             for each row
-            execute procedure quartz.quartz_timers_after_update();
+            execute procedure quartz.quartz_timers_after_update({args});
         create or replace trigger quartz_timers_before_delete
-            before delete on {}
+            before delete on {rel}
             for each row
-            execute procedure quartz.quartz_timers_before_delete();
+            execute procedure quartz.quartz_timers_before_delete({args});
         create or replace trigger quartz_timers_after_delete
-            after delete on {}
+            after delete on {rel}
             for each row
-            execute procedure quartz.quartz_timers_after_delete();
+            execute procedure quartz.quartz_timers_after_delete({args});
         "#,
-        rel, rel, rel, rel, rel, rel
+        rel = timer_table.qualified_name(),
+        args = args
     );
 
     client.update(query.as_str(), None, None).map(|_| ())
 }
 
 pub fn deactivate_timers(rel: &str) {
-    if let Err(e) = Spi::connect(|mut client| self::deactivate_timers_with_client(&mut client, rel))
-    {
+    if let Err(e) = Spi::connect(|mut client| self::deactivate_timers_with_client(&mut client, rel)) {
         error!("quartz.deactivate_timers(): {}", e);
     }
 }
 
-fn deactivate_timers_with_client<'a>(
+fn deactivate_timers_with_client<'a>(client: &mut SpiClient<'a>, rel: &str) -> Result<(), SpiError> {
+    let timer_table = self::find_timer_table_by_name(client, rel)?;
+
+    self::drop_triggers_with_client(client, &timer_table)?;
+
+    if !timer_table.enabled {
+        return Ok(());
+    }
+
+    let args = vec![(PgOid::Custom(pgrx::pg_sys::OIDOID), timer_table.relid.into_datum())];
+
+    client.update(
+        "update quartz.timer_relations set enabled = false where relid = $1",
+        None,
+        Some(args),
+    )?;
+
+    // Untracking the table disarms its timers.
+    let event = TimerSubsystemEvent::UntrackTimersTable {
+        table_oid: timer_table.relid,
+    };
+
+    if !TimerHandle::get().enqueue_event_on_commit(event) {
+        error!("quartz.deactivate_timers(): failed to enqueue event");
+    }
+
+    Ok(())
+}
+
+/// Drop the triggers of a timers table.
+fn drop_triggers_with_client<'a>(
     client: &mut SpiClient<'a>,
-    rel: &str,
+    timer_table: &TimerTableData,
 ) -> Result<(), SpiError> {
     let query = format!(
         r#"
-        drop trigger if exists quartz_timers_before_insert on {};
-        drop trigger if exists quartz_timers_after_insert on {};
-        drop trigger if exists quartz_timers_before_update on {};
-        drop trigger if exists quartz_timers_after_update on {};
-        drop trigger if exists quartz_timers_before_delete on {};
-        drop trigger if exists quartz_timers_after_delete on {};
+        drop trigger if exists quartz_timers_before_insert on {rel};
+        drop trigger if exists quartz_timers_after_insert on {rel};
+        drop trigger if exists quartz_timers_before_update on {rel};
+        drop trigger if exists quartz_timers_after_update on {rel};
+        drop trigger if exists quartz_timers_before_delete on {rel};
+        drop trigger if exists quartz_timers_after_delete on {rel};
         "#,
-        rel, rel, rel, rel, rel, rel
+        rel = timer_table.qualified_name()
     );
 
     client.update(query.as_str(), None, None).map(|_| ())
 }
+
+pub fn scheduled_timers() -> Vec<(pg_sys::Oid, i64, TimestampWithTimeZone, Interval)> {
+    let now = Local::now();
+
+    TimerHandle::get()
+        .scheduled_timers()
+        .into_iter()
+        .map(|timer| {
+            (
+                timer.table_oid,
+                timer.id,
+                timestamp::chrono_to_pg(timer.expires_at),
+                timestamp::chrono_to_pg_interval(timer.deadline - now),
+            )
+        })
+        .collect()
+}
+
+/// A row of the runtime statistics of the current database.
+pub type StatsRow = (
+    Option<pg_sys::Oid>,
+    i64,
+    i64,
+    i64,
+    i64,
+    i64,
+    Vec<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+);
+
+/// The runtime statistics of the current database: one row per timers table,
+/// followed by a row with the totals and the queue statistics of the database,
+/// which has no relid.
+pub fn stats() -> Vec<StatsRow> {
+    let slot = match DatabaseSlots::get().find(unsafe { pg_sys::MyDatabaseId }) {
+        Some(value) => value,
+        None => return Vec::new(),
+    };
+
+    let database = stats::database(slot);
+    let mut rows = Vec::new();
+    let mut totals = (0, 0, 0, 0, 0, vec![0; stats::FIRE_LATENCY_BUCKETS]);
+
+    for (relid, table) in database.tables() {
+        let row = (
+            table.created.get(),
+            table.fired.get(),
+            table.failed.get(),
+            table.cancelled.get(),
+            table.missed.get(),
+            table.fire_latency(),
+        );
+
+        totals.0 += row.0;
+        totals.1 += row.1;
+        totals.2 += row.2;
+        totals.3 += row.3;
+        totals.4 += row.4;
+        totals.5.iter_mut().zip(row.5.iter()).for_each(|(total, count)| *total += count);
+
+        rows.push((Some(relid), row.0, row.1, row.2, row.3, row.4, row.5, None, None, None, None));
+    }
+
+    rows.push((
+        None,
+        totals.0,
+        totals.1,
+        totals.2,
+        totals.3,
+        totals.4,
+        totals.5,
+        Some(database.timer_queue_overflows.get()),
+        TimerHandle::get().queue_depth().map(|depth| depth as i64),
+        Some(database.worker_queue_overflows.get()),
+        WorkersHandle::get().queue_depth().map(|depth| depth as i64),
+    ));
+
+    rows
+}
+
+pub fn reset_stats() {
+    if let Some(slot) = DatabaseSlots::get().find(unsafe { pg_sys::MyDatabaseId }) {
+        stats::database(slot).reset();
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    fn create_orders_table() {
+        Spi::run(
+            r#"
+            create table orders (
+                order_id bigint primary key,
+                payment_deadline timestamp with time zone not null,
+                fired_at timestamp with time zone,
+                completed_at timestamp with time zone
+            )
+            "#,
+        )
+        .unwrap();
+    }
+
+    fn column_count() -> Option<i64> {
+        Spi::get_one::<i64>(
+            "select count(*) from pg_attribute where attrelid = 'orders'::regclass and attnum > 0 and not attisdropped",
+        )
+        .unwrap()
+    }
+
+    #[pg_test]
+    fn test_register_timers_table_without_optional_columns() {
+        create_orders_table();
+
+        Spi::run(
+            r#"select quartz.register_timers_table('orders', '{"id": "order_id", "expires_at": "payment_deadline"}')"#,
+        )
+        .unwrap();
+
+        assert_eq!(column_count(), Some(4));
+
+        let mapping = Spi::get_one::<bool>(
+            r#"
+            select column_mapping ->> 'id' = 'order_id'
+                and column_mapping ? 'attempts'
+                and column_mapping ->> 'attempts' is null
+            from quartz.timer_relations
+            where relid = 'orders'::regclass
+            "#,
+        );
+        assert_eq!(mapping, Ok(Some(true)));
+    }
+
+    #[pg_test]
+    fn test_register_timers_table_with_added_columns() {
+        create_orders_table();
+
+        Spi::run(
+            r#"select quartz.register_timers_table('orders', '{"id": "order_id", "expires_at": "payment_deadline"}', true)"#,
+        )
+        .unwrap();
+
+        // attempts, last_error, repeat_every, schedule, repeat_until,
+        // max_occurrences, occurrences and missed_at are added.
+        assert_eq!(column_count(), Some(12));
+    }
+
+    #[pg_test(error = "quartz.set_retry_policy(): retrying timers requires a column of role \"attempts\" in orders")]
+    fn test_retry_without_attempts_column() {
+        create_orders_table();
+
+        Spi::run(
+            r#"select quartz.register_timers_table('orders', '{"id": "order_id", "expires_at": "payment_deadline"}')"#,
+        )
+        .unwrap();
+        Spi::run("select quartz.set_retry_policy('orders', 3)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_ddl_as_unprivileged_role() {
+        Spi::run("create role quartz_unprivileged").unwrap();
+        Spi::run("grant create on schema public to quartz_unprivileged").unwrap();
+        Spi::run("set role quartz_unprivileged").unwrap();
+
+        Spi::run("create table notes (note_id bigint primary key)").unwrap();
+        Spi::run("alter table notes add column body text").unwrap();
+        Spi::run("drop table notes").unwrap();
+
+        Spi::run("reset role").unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+// src/launcher.rs
+
+//! The launcher, which starts a timer subsystem for every database that has
+//! the quartz extension installed.
+
+use pgrx::bgworkers::*;
+use pgrx::log;
+use pgrx::pg_shmem_init;
+use pgrx::pg_sys::Oid;
+use pgrx::prelude::*;
+use pgrx::shmem::*;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+use std::time::Duration as StdDuration;
+
+use crate::commands;
+use crate::config;
+use crate::shmem::SharedInit;
+use crate::shmem::SharedObject;
+use crate::timer;
+
+/// The file in the data directory that lists the OIDs of the databases that
+/// have the extension installed, so that only their subsystems are started
+/// when the server starts.
+const DATABASES_FILE: &str = "quartz.databases";
+
+/// The states of the extension that subsystems report for their database.
+const EXTENSION_UNREPORTED: u8 = 0;
+const EXTENSION_INSTALLED: u8 = 1;
+const EXTENSION_NOT_INSTALLED: u8 = 2;
+
+/// Initialize the launcher.
+pub(crate) fn pg_init() {
+    log!("quartz-launcher: pg_init");
+
+    pg_shmem_init!(DATABASE_SLOTS);
+
+    BackgroundWorkerBuilder::new("quartz-launcher")
+        .set_library("quartz")
+        .set_function("quartz_launcher_main")
+        .set_argument(0.into_datum())
+        .set_type("quartz-launcher")
+        .set_start_time(BgWorkerStartTime::RecoveryFinished)
+        .set_restart_time(config::restart_time())
+        .enable_shmem_access(None)
+        .enable_spi_access()
+        .load();
+}
+
+/// The slots that assign the shared queues of the per-database subsystems to
+/// databases.
+///
+/// A slot is claimed by the first backend or subsystem that needs it, and
+/// stays assigned to its database until the server restarts. This way, events
+/// that are still queued when a subsystem stops can never be consumed by the
+/// subsystem of another database.
+#[derive(Default)]
+pub struct DatabaseSlots {
+    // The OIDs of the databases the slots are assigned to, or 0 if free.
+    databases: [AtomicU32; config::MAX_DATABASES],
+
+    // Whether events have been enqueued for the subsystem of the database,
+    // which therefore has to be running.
+    pending: [AtomicBool; config::MAX_DATABASES],
+
+    // Whether the subsystem of the database found the extension installed,
+    // until the launcher takes the report.
+    extension: [AtomicU8; config::MAX_DATABASES],
+}
+
+impl SharedInit for DatabaseSlots {
+    unsafe fn init(ptr: *mut Self) {
+        // Free slots without pending events or reports are all zeroes.
+        ptr.write_bytes(0, 1);
+    }
+}
+
+/// The slots of the per-database subsystems.
+static DATABASE_SLOTS: SharedObject<DatabaseSlots> = SharedObject::new("quartz-database-slots");
+
+impl DatabaseSlots {
+    pub fn get() -> &'static Self {
+        DATABASE_SLOTS.get()
+    }
+
+    /// Find the slot of a database.
+    pub fn find(&self, database: Oid) -> Option<usize> {
+        self.databases
+            .iter()
+            .position(|value| value.load(Ordering::Acquire) == database.as_u32())
+    }
+
+    /// Find the slot of a database, claiming a free one if it has none yet.
+    ///
+    /// Returns None if all slots are assigned to other databases.
+    pub fn claim(&self, database: Oid) -> Option<usize> {
+        if let Some(slot) = self.find(database) {
+            return Some(slot);
+        }
+
+        // Slots are claimed in order, so that concurrent claims for the same
+        // database end up in the same slot.
+        for (slot, value) in self.databases.iter().enumerate() {
+            match value.compare_exchange(0, database.as_u32(), Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Some(slot),
+                Err(current) if current == database.as_u32() => return Some(slot),
+                Err(_) => continue,
+            }
+        }
+
+        None
+    }
+
+    /// Request the subsystem of the database in a slot to be running.
+    pub fn mark_pending(&self, slot: usize) {
+        self.pending[slot].store(true, Ordering::Release);
+    }
+
+    /// Report whether the extension is installed in the database of a slot.
+    pub fn report_extension(&self, slot: usize, installed: bool) {
+        let state = if installed {
+            EXTENSION_INSTALLED
+        } else {
+            EXTENSION_NOT_INSTALLED
+        };
+
+        self.extension[slot].store(state, Ordering::Release);
+    }
+
+    /// Take the reports of the subsystems, as the databases along with whether
+    /// they have the extension installed.
+    fn take_extension_reports(&self) -> Vec<(Oid, bool)> {
+        self.databases
+            .iter()
+            .zip(self.extension.iter())
+            .map(|(database, state)| (database.load(Ordering::Acquire), state.swap(EXTENSION_UNREPORTED, Ordering::AcqRel)))
+            .filter(|(database, state)| *database != 0 && *state != EXTENSION_UNREPORTED)
+            // The slots only ever hold OIDs of databases.
+            .map(|(database, state)| (unsafe { Oid::from_u32_unchecked(database) }, state == EXTENSION_INSTALLED))
+            .collect()
+    }
+
+    /// Take the pending requests, as the databases that need a running
+    /// subsystem.
+    fn take_pending(&self) -> Vec<Oid> {
+        self.databases
+            .iter()
+            .zip(self.pending.iter())
+            .filter(|(_, pending)| pending.swap(false, Ordering::AcqRel))
+            .map(|(database, _)| database.load(Ordering::Acquire))
+            .filter(|database| *database != 0)
+            // The slots only ever hold OIDs of databases.
+            .map(|database| unsafe { Oid::from_u32_unchecked(database) })
+            .collect()
+    }
+}
+
+/// Main function of the launcher.
+///
+/// At startup, a timer subsystem is started for every database that had the
+/// extension installed when the server stopped. Without a record of them, e.g.
+/// when the server starts with quartz for the first time, a subsystem is
+/// started for every database, which exits right away if the extension is not
+/// installed there. Afterwards, subsystems are started on demand, as soon as
+/// events are enqueued for a database that has none running.
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn quartz_launcher_main(_arg: pg_sys::Datum) {
+    log!("quartz-launcher: starting");
+
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGHUP | SignalWakeFlags::SIGTERM);
+    BackgroundWorker::connect_worker_to_spi(
+        config::database().as_deref(),
+        config::role().as_deref(),
+    );
+
+    let mut launcher = Launcher::new();
+
+    match BackgroundWorker::transaction(|| Spi::connect(|client| commands::find_databases(&client))) {
+        Ok(databases) => launcher.start_subsystems(databases),
+        Err(e) => error!("quartz-launcher: failed to find databases: {}", e),
+    }
+
+    while BackgroundWorker::wait_latch(Some(StdDuration::from_secs(1))) {
+        if BackgroundWorker::sigterm_received() {
+            break;
+        }
+
+        launcher.start_pending_subsystems();
+        launcher.update_databases();
+    }
+
+    log!("quartz-launcher: bye bye");
+}
+
+/// The launcher, keeping track of the subsystems it started.
+struct Launcher {
+    subsystems: HashMap<Oid, DynamicBackgroundWorker>,
+
+    // The databases that have the extension installed, as recorded in the
+    // databases file.
+    databases: HashSet<Oid>,
+}
+
+impl Launcher {
+    fn new() -> Self {
+        Self {
+            subsystems: HashMap::new(),
+            databases: HashSet::new(),
+        }
+    }
+
+    /// Start the subsystems of the recorded databases, or of all databases if
+    /// there is no record of them.
+    fn start_subsystems(&mut self, databases: Vec<(Oid, String)>) {
+        let recorded = match Self::read_databases_file() {
+            Ok(value) => value,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warning!("quartz-launcher: failed to read \"{}\": {}", DATABASES_FILE, e);
+                }
+
+                for (database, name) in databases {
+                    self.start_subsystem(database, name.as_str());
+                }
+
+                return;
+            }
+        };
+
+        for (database, name) in databases {
+            if !recorded.contains(&database) {
+                continue;
+            }
+
+            // The slot is claimed right away, so that the subsystem can report
+            // if the extension is no longer installed.
+            if DatabaseSlots::get().claim(database).is_none() {
+                warning!(
+                    "quartz-launcher: no slot for database \"{}\", at most {} databases are supported",
+                    name,
+                    config::MAX_DATABASES
+                );
+
+                continue;
+            }
+
+            self.databases.insert(database);
+            self.start_subsystem(database, name.as_str());
+        }
+
+        // Databases that have been dropped in the meantime are forgotten.
+        if self.databases != recorded {
+            self.write_databases_file();
+        }
+    }
+
+    /// Start the subsystems of databases with pending events, unless they are
+    /// already running.
+    fn start_pending_subsystems(&mut self) {
+        for database in DatabaseSlots::get().take_pending() {
+            if self.is_running(database) {
+                continue;
+            }
+
+            let name = BackgroundWorker::transaction(|| {
+                Spi::connect(|client| commands::find_database_name(&client, database))
+            });
+
+            match name {
+                Ok(Some(name)) => self.start_subsystem(database, name.as_str()),
+                Ok(None) => warning!("quartz-launcher: database {} does not exist", database),
+                Err(e) => warning!("quartz-launcher: failed to find database {}: {}", database, e),
+            }
+        }
+    }
+
+    /// Record the databases whose subsystems found the extension installed or
+    /// not since the last call.
+    fn update_databases(&mut self) {
+        let mut changed = false;
+
+        for (database, installed) in DatabaseSlots::get().take_extension_reports() {
+            changed |= if installed {
+                self.databases.insert(database)
+            } else {
+                self.databases.remove(&database)
+            };
+        }
+
+        if changed {
+            self.write_databases_file();
+        }
+    }
+
+    fn start_subsystem(&mut self, database: Oid, name: &str) {
+        log!("quartz-launcher: starting timer subsystem for database \"{}\"", name);
+
+        self.subsystems.insert(database, timer::start(name));
+    }
+
+    fn is_running(&self, database: Oid) -> bool {
+        matches!(
+            self.subsystems.get(&database).map(|worker| worker.pid()),
+            Some(Ok(_)) | Some(Err(BackgroundWorkerStatus::NotYetStarted))
+        )
+    }
+
+    /// Read the databases file, which holds one database OID per line.
+    fn read_databases_file() -> io::Result<HashSet<Oid>> {
+        let contents = fs::read_to_string(DATABASES_FILE)?;
+
+        contents
+            .lines()
+            .map(|line| {
+                line.parse::<u32>()
+                    .ok()
+                    .filter(|oid| *oid != 0)
+                    // Only non-zero numbers are accepted, which are valid OIDs.
+                    .map(|oid| unsafe { Oid::from_u32_unchecked(oid) })
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid OID \"{}\"", line)))
+            })
+            .collect()
+    }
+
+    /// Replace the databases file, through a temporary file so that it is never
+    /// left half written.
+    fn write_databases_file(&self) {
+        let mut databases = self.databases.iter().map(|database| database.as_u32()).collect::<Vec<_>>();
+        databases.sort_unstable();
+
+        let contents = databases.iter().map(|database| format!("{}\n", database)).collect::<String>();
+        let temporary = format!("{}.tmp", DATABASES_FILE);
+
+        let result = fs::File::create(temporary.as_str())
+            .and_then(|mut file| {
+                file.write_all(contents.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(temporary.as_str(), DATABASES_FILE));
+
+        if let Err(e) = result {
+            warning!("quartz-launcher: failed to write \"{}\": {}", DATABASES_FILE, e);
+        }
+    }
+}
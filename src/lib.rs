@@ -1,14 +1,19 @@
 // src/lib.rs
 
-mod commands;  /// Internal SQL query commands wrapping SPI calls.
-mod config;    /// Configuration for the quartz extension.
-mod functions; /// SQL functions.
-mod shmem;     /// Shared memory.
-mod timer;     /// Timer implementation.
-mod timestamp; /// Timestamp conversion between Postgres and Chrono.
-mod triggers;  /// Triggers for timer tables.
-mod types;     /// Common types.
-mod workers;   /// Background worker for timer execution.
+mod commands;       // Internal SQL query commands wrapping SPI calls.
+mod config;         // Configuration for the quartz extension.
+mod cron;           // Cron expressions for scheduled timers.
+mod deadlines;      // Deadlines of pending timers.
+mod functions;      // SQL functions.
+mod launcher;       // Launcher of the per-database timer subsystems.
+mod shmem;          // Shared memory.
+mod stats;          // Runtime statistics.
+mod subtransaction; // Internal subtransactions.
+mod timer;          // Timer implementation.
+mod timestamp;      // Timestamp conversion between Postgres and Chrono.
+mod triggers;       // Triggers for timer tables.
+mod types;          // Common types.
+mod workers;        // Background worker for timer execution.
 
 use pgrx::prelude::*;
 
@@ -17,13 +22,18 @@ pgrx::extension_sql_file!(
     name = "init", // fixme: Why does pgrx panic on "sql/init.sql"?
 );
 
+pgrx::extension_sql_file!("../sql/stats.sql", name = "stats", finalize);
+
 pgrx::pg_module_magic!();
 
 #[allow(non_snake_case)]
 #[pg_guard]
 pub extern "C" fn _PG_init() {
-    workers::pg_init(); // Initialize workers sub-module.
-    timer::pg_init();   // Initialize timer sub-module.
+    config::pg_init();   // Register configuration settings.
+    workers::pg_init();  // Initialize workers sub-module.
+    timer::pg_init();    // Initialize timer sub-module.
+    launcher::pg_init(); // Initialize launcher sub-module.
+    stats::pg_init();    // Initialize statistics sub-module.
 }
 
 /// This module manages the SQL schema for this extension, and the exported
@@ -55,6 +65,10 @@ mod quartz {
 
     /// Activate timers for a relation.
     ///
+    /// The relation is registered as a timers table if it is not one yet.
+    /// Its timers are tracked and its pending timers are armed, also after
+    /// restarts.
+    ///
     /// Relation can be:
     ///
     /// - **schema**.**table** - fully qualified
@@ -67,6 +81,9 @@ mod quartz {
 
     /// Deactivate timers for a relation.
     ///
+    /// The timers of the relation are no longer tracked and its armed timers
+    /// are disarmed, until it is activated again.
+    ///
     /// Relation can be:
     ///
     /// - **schema**.**table** - fully qualified
@@ -83,9 +100,309 @@ mod quartz {
     ///
     /// - **schema**.**table** - fully qualified
     /// - **table**            - assumes current schema
+    ///
+    /// If `payload` is set, the table gets a `payload jsonb` column for data
+    /// describing what each timer is for. Callbacks receive it with the rest
+    /// of the row, and notifications include it.
+    ///
+    /// Cron expressions in the `schedule` column and intervals in the
+    /// `repeat_every` column are evaluated in the time zone of the server
+    /// process, i.e. its `TZ` environment variable or the system time zone,
+    /// not in the `TimeZone` setting.
+    #[pg_guard]
+    #[pg_extern]
+    fn create_timers_table(rel: &str, payload: default!(bool, false)) {
+        crate::functions::create_timers_table(rel, payload)
+    }
+
+    /// Turn an existing table into a timers table.
+    ///
+    /// `mapping` maps the roles of the columns of timers tables onto columns
+    /// of the table, e.g. `{"id": "order_id", "expires_at": "payment_deadline"}`.
+    /// Roles that are not mapped use columns named after them. The columns of
+    /// `id`, `expires_at`, `fired_at` and `completed_at` must exist. The other
+    /// roles are optional, and the table goes without retries, recurrence,
+    /// missed timers or a payload if it lacks their columns. Map a role onto
+    /// `null` to leave it without a column.
+    ///
+    /// The table is only altered if `add_columns` is set, in which case it
+    /// gets the columns that quartz manages itself, such as `fired_at` or
+    /// `attempts`, if it lacks them.
+    ///
+    /// Without an `attempts` column, failures are not recorded on the rows,
+    /// so a timer whose firing failed is fired again once the timer
+    /// subsystem restarts.
+    ///
+    /// The existing rows of the table become timers, so rows that have
+    /// already expired fire right away. Drop the table with `keep_data` to
+    /// turn it back into a regular table.
+    ///
+    /// Relation can be:
+    ///
+    /// - **schema**.**table** - fully qualified
+    /// - **table**            - assumes current schema
+    #[pg_guard]
+    #[pg_extern]
+    fn register_timers_table(
+        rel: &str,
+        mapping: default!(pgrx::JsonB, "'{}'"),
+        add_columns: default!(bool, false),
+    ) {
+        crate::functions::register_timers_table(rel, mapping, add_columns)
+    }
+
+    /// Drop a timers table, untracking all of its timers.
+    ///
+    /// If `keep_data` is set, the table is only turned back into a regular
+    /// table and its rows are kept.
+    ///
+    /// Relation can be:
+    ///
+    /// - **schema**.**table** - fully qualified
+    /// - **table**            - assumes current schema
     #[pg_guard]
     #[pg_extern]
-    fn create_timers_table(rel: &str) {
-        crate::functions::create_timers_table(rel)
+    fn drop_timers_table(rel: &str, keep_data: default!(bool, false)) {
+        crate::functions::drop_timers_table(rel, keep_data)
+    }
+
+    /// Set the function that is called when a timer of a relation fires.
+    ///
+    /// The callback must take a single argument of the row type of the
+    /// relation and is invoked with the fired row, in the same transaction
+    /// that marks the timer as fired. Passing null removes the callback.
+    ///
+    /// Only the owner of the relation may set its callback. The callback runs
+    /// with the privileges of the owner, as a security-restricted operation,
+    /// so it cannot e.g. change the role or create temporary tables.
+    ///
+    /// Relation can be:
+    ///
+    /// - **schema**.**table** - fully qualified
+    /// - **table**            - assumes current schema
+    #[pg_guard]
+    #[pg_extern]
+    fn set_timer_callback(rel: &str, callback: Option<&str>) {
+        crate::functions::set_timer_callback(rel, callback)
+    }
+
+    /// Set the channel on which a notification is sent when a timer of a
+    /// relation fires. Passing null disables notifications.
+    ///
+    /// The notification is delivered when the transaction that fires the
+    /// timer commits. Its payload is a JSON object with the keys:
+    ///
+    /// - **timer**      - the `quartz.timer_id` of the timer, with the table
+    ///                    given by OID, e.g. `(16384,42)`
+    /// - **schema**     - the schema of the relation
+    /// - **table**      - the name of the relation
+    /// - **id**         - the ID of the timer in the relation
+    /// - **expires_at** - when the timer expired
+    /// - **fired_at**   - when the timer fired
+    /// - **payload**    - the payload of the timer, or null if the relation
+    ///                    has no payload column
+    ///
+    /// Relation can be:
+    ///
+    /// - **schema**.**table** - fully qualified
+    /// - **table**            - assumes current schema
+    #[pg_guard]
+    #[pg_extern]
+    fn set_timer_notify_channel(rel: &str, channel: Option<&str>) {
+        crate::functions::set_timer_notify_channel(rel, channel)
+    }
+
+    /// Set the policy for retrying timers of a relation whose firing failed.
+    ///
+    /// A timer is fired at most `max_attempts` times. The delay between
+    /// attempts starts at `backoff`, doubles with every attempt up to
+    /// `backoff_max`, and is randomly spread out by the `jitter` fraction. A
+    /// failed timer expires again at its next attempt, at least a second
+    /// later, so that the retry survives a restart.
+    ///
+    /// Failures are counted in the column of the `attempts` role, so only
+    /// relations that have one may retry timers.
+    ///
+    /// If `redeliver` is set, timers that have fired but have not been
+    /// completed when the timer subsystem starts (e.g. after a crash) are
+    /// fired again, as long as they have attempts left.
+    ///
+    /// Relation can be:
+    ///
+    /// - **schema**.**table** - fully qualified
+    /// - **table**            - assumes current schema
+    #[pg_guard]
+    #[pg_extern]
+    fn set_retry_policy(
+        rel: &str,
+        max_attempts: i32,
+        backoff: default!(Interval, "'1 second'"),
+        backoff_max: default!(Interval, "'1 hour'"),
+        jitter: default!(f64, 0.1),
+        redeliver: default!(bool, false),
+    ) {
+        crate::functions::set_retry_policy(rel, max_attempts, backoff, backoff_max, jitter, redeliver)
+    }
+
+    /// Set the policy for timers of a relation that misfired, i.e. that are
+    /// only armed more than `threshold` after their expiration, e.g. because
+    /// the server was down.
+    ///
+    /// The policy is one of:
+    ///
+    /// - **fire_all**    - fire all misfired timers right away
+    /// - **fire_latest** - fire only the latest misfired timer with the same
+    ///                     value in the `key` column, or in the whole relation
+    ///                     if there is no key, and mark the others as missed
+    /// - **skip**        - mark misfired timers as missed without firing them
+    /// - **catch_up**    - fire misfired timers at a `rate` of timers per second
+    ///
+    /// Recurring timers that are not fired are moved to their next expiration
+    /// instead of being marked as missed.
+    ///
+    /// **fire_latest** and **skip** require a column of the `missed_at` role.
+    ///
+    /// Relation can be:
+    ///
+    /// - **schema**.**table** - fully qualified
+    /// - **table**            - assumes current schema
+    #[pg_guard]
+    #[pg_extern]
+    fn set_misfire_policy(
+        rel: &str,
+        policy: &str,
+        threshold: default!(Interval, "'1 minute'"),
+        key: default!(Option<&str>, "NULL"),
+        rate: default!(Option<f64>, "NULL"),
+    ) {
+        crate::functions::set_misfire_policy(rel, policy, threshold, key, rate)
+    }
+
+    /// Acknowledge that the action of a fired timer has been completed.
+    ///
+    /// Relation can be:
+    ///
+    /// - **schema**.**table** - fully qualified
+    /// - **table**            - assumes current schema
+    ///
+    /// `quartz.complete_timer(quartz.timer_id)` completes a timer by its
+    /// global identity instead.
+    #[pg_guard]
+    #[pg_extern]
+    fn complete_timer(rel: &str, id: i64) {
+        crate::functions::complete_timer(rel, id)
+    }
+
+    /// Acknowledge that the actions of multiple fired timers have been
+    /// completed. Either all of the timers are completed, or none of them.
+    ///
+    /// Relation can be:
+    ///
+    /// - **schema**.**table** - fully qualified
+    /// - **table**            - assumes current schema
+    ///
+    /// `quartz.complete_timers(quartz.timer_id[])` completes timers by their
+    /// global identity instead, which may span multiple tables.
+    #[pg_guard]
+    #[pg_extern]
+    fn complete_timers(rel: &str, ids: Vec<i64>) {
+        crate::functions::complete_timers(rel, ids)
+    }
+
+    /// List the timers that the timer subsystem of the current database has
+    /// armed, ordered by the time at which they fire.
+    ///
+    /// `remaining` is the time until the timer fires, which is later than its
+    /// `expires_at` if it is caught up after a misfire, and negative if it is
+    /// overdue because the workers are busy.
+    ///
+    /// `(relid, id)::quartz.timer_id` is the global identity of a timer, as
+    /// shown in the server log. Only `quartz.complete_timer` and
+    /// `quartz.complete_timers` accept it: the other functions manage whole
+    /// tables, and timers are rescheduled or cancelled through their rows.
+    #[pg_extern]
+    fn scheduled_timers() -> TableIterator<
+        'static,
+        (
+            name!(relid, pg_sys::Oid),
+            name!(id, i64),
+            name!(expires_at, TimestampWithTimeZone),
+            name!(remaining, Interval),
+        ),
+    > {
+        TableIterator::new(crate::functions::scheduled_timers())
+    }
+
+    /// List the runtime statistics of the current database, which back the
+    /// `quartz.stats` view.
+    #[pg_extern]
+    #[allow(clippy::type_complexity)]
+    fn timer_stats() -> TableIterator<
+        'static,
+        (
+            name!(relid, Option<pg_sys::Oid>),
+            name!(created, i64),
+            name!(fired, i64),
+            name!(failed, i64),
+            name!(cancelled, i64),
+            name!(missed, i64),
+            name!(fire_latency_histogram, Vec<i64>),
+            name!(timer_queue_overflows, Option<i64>),
+            name!(timer_queue_depth, Option<i64>),
+            name!(worker_queue_overflows, Option<i64>),
+            name!(worker_queue_depth, Option<i64>),
+        ),
+    > {
+        TableIterator::new(crate::functions::stats())
+    }
+
+    /// Reset the runtime statistics of the current database.
+    #[pg_guard]
+    #[pg_extern]
+    fn reset_stats() {
+        crate::functions::reset_stats()
+    }
+
+    /// Untrack a timers table that has been dropped.
+    ///
+    /// This is called by the `quartz_sql_drop` event trigger, once the table
+    /// has been removed from `quartz.timer_relations`.
+    #[pg_guard]
+    #[pg_extern]
+    fn timers_table_dropped(relid: pg_sys::Oid) {
+        crate::functions::timers_table_dropped(relid)
+    }
+
+    /// Check that a timers table still has the columns that quartz requires,
+    /// raising an error otherwise.
+    ///
+    /// This is called by the `quartz_ddl_command_end` event trigger, so that
+    /// ALTERs that break timers tables are rejected.
+    #[pg_guard]
+    #[pg_extern]
+    fn check_timers_table(relid: pg_sys::Oid) {
+        crate::functions::check_timers_table(relid)
+    }
+
+    /// Check whether a text is a valid cron expression.
+    ///
+    /// This backs the check constraint on the `schedule` column of timers
+    /// tables. See `create_timers_table` for the time zone that schedules are
+    /// evaluated in.
+    #[pg_guard]
+    #[pg_extern(immutable, parallel_safe)]
+    fn is_valid_cron(expression: &str) -> bool {
+        crate::cron::CronSchedule::parse(expression).is_ok()
+    }
+}
+
+/// Setup of the Postgres instance that `cargo pgrx test` runs the tests in.
+#[cfg(test)]
+pub mod pg_test {
+    pub fn setup(_options: Vec<&str>) {}
+
+    pub fn postgresql_conf_options() -> Vec<&'static str> {
+        // The timer subsystems allocate their shared memory on startup.
+        vec!["shared_preload_libraries = 'quartz'"]
     }
 }
@@ -1,11 +1,15 @@
 // src/shmem.rs
 
+use heapless::mpmc::MpMcQueue;
 use once_cell::sync::OnceCell;
 
 use pgrx::prelude::*;
 use pgrx::shmem::PgSharedMemoryInitialization;
 
 use std::ffi::CString;
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 
 /// An object residing in PostgreSQL's shared memory.
 pub struct SharedObject<T> {
@@ -24,14 +28,13 @@ impl<T> SharedObject<T> {
 
     /// Attach a value to this shared object.
     pub fn attach(&self, value: *mut T) {
-        self.inner.set(value).expect(
-            format!(
+        self.inner.set(value).unwrap_or_else(|_| {
+            panic!(
                 "SharedObject<{}> {} has already been initialized",
                 std::any::type_name::<T>(),
                 self.name,
             )
-            .as_str(),
-        );
+        });
     }
 
     /// Get a reference to the value attached to this shared object.
@@ -39,23 +42,48 @@ impl<T> SharedObject<T> {
         unsafe {
             self.inner
                 .get()
-                .expect(
-                    format!(
+                .unwrap_or_else(|| {
+                    panic!(
                         "SharedObject<{}> {} has not been initialized",
                         std::any::type_name::<T>(), self.name,
-                    ).as_str())
+                    )
+                })
                 .as_ref()
-                .expect(
-                    format!(
+                .unwrap_or_else(|| {
+                    panic!(
                         "SharedObject<{}> {} has been initialized with a null pointer. This is an internal error.",
                         std::any::type_name::<T>(), self.name,
-                    ).as_str(),
-                )
+                    )
+                })
         }
     }
 }
 
-impl<T: Default> PgSharedMemoryInitialization for SharedObject<T> {
+/// A value that is initialized in place in shared memory.
+///
+/// Shared objects can be too large to be built on the stack and then moved
+/// into shared memory, so they are written piece by piece.
+pub trait SharedInit {
+    /// Initialize the value behind a pointer.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be valid for writes and properly aligned.
+    unsafe fn init(ptr: *mut Self);
+}
+
+/// Arrays are initialized one element at a time.
+impl<T: Default, const N: usize> SharedInit for [T; N] {
+    unsafe fn init(ptr: *mut Self) {
+        let first = ptr as *mut T;
+
+        for i in 0..N {
+            first.add(i).write(T::default());
+        }
+    }
+}
+
+impl<T: SharedInit> PgSharedMemoryInitialization for SharedObject<T> {
     fn pg_init(&'static self) {
         unsafe {
             pg_sys::RequestAddinShmemSpace(std::mem::size_of::<T>());
@@ -81,13 +109,164 @@ impl<T: Default> PgSharedMemoryInitialization for SharedObject<T> {
             }
 
             self.attach(fv_shmem);
-            let object = T::default();
-            std::ptr::copy(&object, fv_shmem, 1);
+            T::init(fv_shmem);
 
             pg_sys::LWLockRelease(addin_shmem_init_lock);
         }
     }
 }
 
-unsafe impl<T> Send for SharedObject<T> where T: Default {}
-unsafe impl<T> Sync for SharedObject<T> where T: Default {}
+unsafe impl<T> Send for SharedObject<T> where T: SharedInit {}
+unsafe impl<T> Sync for SharedObject<T> where T: SharedInit {}
+
+/// A bounded queue residing in PostgreSQL's shared memory.
+///
+/// The capacity `N` of the underlying queue is fixed at compile time, but the
+/// number of queued items can be limited further at runtime.
+pub struct SharedQueue<T, const N: usize> {
+    queue: MpMcQueue<T, N>,
+    len: AtomicUsize,
+}
+
+impl<T, const N: usize> SharedQueue<T, N> {
+    /// Enqueue an item, unless the queue already holds `limit` items.
+    ///
+    /// Returns the item if it could not be enqueued.
+    pub fn enqueue(&self, item: T, limit: usize) -> Result<(), T> {
+        if !self.reserve(limit) {
+            return Err(item);
+        }
+
+        self.enqueue_reserved(item).inspect_err(|_| self.release())
+    }
+
+    /// Reserve room for an item, unless the queue already holds `limit`
+    /// items, including reserved ones.
+    ///
+    /// The reservation must be used by `enqueue_reserved` or given back by
+    /// `release`.
+    pub fn reserve(&self, limit: usize) -> bool {
+        self.len
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |len| {
+                (len < limit).then_some(len + 1)
+            })
+            .is_ok()
+    }
+
+    /// Give back a reservation.
+    pub fn release(&self) {
+        self.len.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// Enqueue an item into reserved room.
+    ///
+    /// This can only fail transiently, while a concurrent dequeue is still
+    /// in progress. The reservation is kept in that case.
+    pub fn enqueue_reserved(&self, item: T) -> Result<(), T> {
+        self.queue.enqueue(item)
+    }
+
+    /// The number of queued items, including reserved ones.
+    pub fn depth(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Dequeue an item, if there is any.
+    pub fn dequeue(&self) -> Option<T> {
+        let item = self.queue.dequeue()?;
+        self.len.fetch_sub(1, Ordering::AcqRel);
+        Some(item)
+    }
+}
+
+impl<T, const N: usize> Default for SharedQueue<T, N> {
+    fn default() -> Self {
+        Self {
+            queue: MpMcQueue::new(),
+            len: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// The latch of a process residing in PostgreSQL's shared memory, so that
+/// other processes can wake it up.
+pub struct SharedLatch(AtomicPtr<pg_sys::Latch>);
+
+impl SharedLatch {
+    /// Make this the latch of the current process.
+    pub fn attach(&self) {
+        self.0.store(unsafe { pg_sys::MyLatch }, Ordering::Release);
+    }
+
+    /// Detach the current process from this latch.
+    pub fn detach(&self) {
+        self.0.store(std::ptr::null_mut(), Ordering::Release);
+    }
+
+    /// The PID of the process attached to this latch, if it is still running.
+    pub fn owner_pid(&self) -> Option<i32> {
+        let latch = self.0.load(Ordering::Acquire);
+
+        if latch.is_null() {
+            return None;
+        }
+
+        // Latches of exited processes are disowned, but stay in shared memory.
+        match unsafe { std::ptr::read_volatile(&(*latch).owner_pid) } {
+            0 => None,
+            pid => Some(pid),
+        }
+    }
+
+    /// Wake up the process attached to this latch, if any.
+    pub fn set(&self) {
+        let latch = self.0.load(Ordering::Acquire);
+
+        if !latch.is_null() {
+            unsafe { pg_sys::SetLatch(latch) };
+        }
+    }
+}
+
+impl Default for SharedLatch {
+    fn default() -> Self {
+        Self(AtomicPtr::new(std::ptr::null_mut()))
+    }
+}
+
+/// A group of latches residing in PostgreSQL's shared memory, e.g. of all
+/// the processes consuming a shared queue.
+pub struct SharedLatches<const N: usize> {
+    latches: [SharedLatch; N],
+    // The number of latches in use, as one past the highest attached index.
+    len: AtomicUsize,
+}
+
+impl<const N: usize> SharedLatches<N> {
+    /// Make the latch at `index` the latch of the current process.
+    pub fn attach(&self, index: usize) {
+        self.latches[index].attach();
+        self.len.fetch_max(index + 1, Ordering::AcqRel);
+    }
+
+    /// Detach the current process from the latch at `index`.
+    pub fn detach(&self, index: usize) {
+        self.latches[index].detach();
+    }
+
+    /// Wake up all processes attached to the latches.
+    pub fn set_all(&self) {
+        for latch in &self.latches[..self.len.load(Ordering::Acquire)] {
+            latch.set();
+        }
+    }
+}
+
+impl<const N: usize> Default for SharedLatches<N> {
+    fn default() -> Self {
+        Self {
+            latches: std::array::from_fn(|_| SharedLatch::default()),
+            len: AtomicUsize::new(0),
+        }
+    }
+}
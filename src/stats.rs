@@ -0,0 +1,206 @@
+// src/stats.rs
+
+//! Runtime statistics of the timer subsystems.
+//!
+//! The statistics are counted in shared memory per database slot, and per
+//! timers table within it. They are kept until the server restarts or they
+//! are reset.
+
+use pgrx::log;
+use pgrx::pg_shmem_init;
+use pgrx::pg_sys::Oid;
+use pgrx::prelude::*;
+use pgrx::shmem::*;
+
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+use crate::config;
+use crate::shmem::SharedObject;
+
+/// The maximum number of timers tables per database that statistics are kept
+/// for. Events of further tables are only counted in the queue statistics.
+pub const MAX_TABLES: usize = 64;
+
+/// The upper bounds of the buckets of the fire latency histograms, in
+/// milliseconds. The last bucket holds all larger latencies.
+pub const FIRE_LATENCY_BOUNDS_MS: [i64; 7] = [1, 10, 100, 1_000, 10_000, 60_000, 600_000];
+
+/// The number of buckets of the fire latency histograms.
+pub const FIRE_LATENCY_BUCKETS: usize = FIRE_LATENCY_BOUNDS_MS.len() + 1;
+
+/// Initialize the statistics.
+pub(crate) fn pg_init() {
+    log!("quartz-stats: pg_init");
+
+    pg_shmem_init!(STATS);
+}
+
+/// The statistics of all database slots.
+static STATS: SharedObject<[DatabaseStats; config::MAX_DATABASES]> = SharedObject::new("quartz-stats");
+
+/// The statistics of the database in a slot.
+pub fn database(slot: usize) -> &'static DatabaseStats {
+    &STATS.get()[slot]
+}
+
+/// A counter residing in shared memory.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn incr(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed) as i64
+    }
+
+    fn reset(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+}
+
+/// The statistics of a database.
+pub struct DatabaseStats {
+    /// The number of timer events that did not fit into the timer subsystem
+    /// queue, and were spilled or dropped.
+    pub timer_queue_overflows: Counter,
+
+    /// The number of fired timers that did not fit into the workers queue,
+    /// and were handed over again later.
+    pub worker_queue_overflows: Counter,
+
+    // The statistics of the timers tables of the database.
+    tables: [TableStats; MAX_TABLES],
+}
+
+impl DatabaseStats {
+    /// The statistics of a timers table, which are claimed on first use.
+    ///
+    /// Returns None if statistics are already kept for too many tables.
+    pub fn table(&self, relid: Oid) -> Option<&TableStats> {
+        if let Some(table) = self.find_table(relid) {
+            return Some(table);
+        }
+
+        // Tables are claimed in order, so that concurrent claims for the
+        // same table end up in the same place.
+        for table in self.tables.iter() {
+            match table
+                .relid
+                .compare_exchange(0, relid.as_u32(), Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return Some(table),
+                Err(current) if current == relid.as_u32() => return Some(table),
+                Err(_) => continue,
+            }
+        }
+
+        None
+    }
+
+    /// The statistics of a timers table, if any are kept for it.
+    pub fn find_table(&self, relid: Oid) -> Option<&TableStats> {
+        self.tables
+            .iter()
+            .find(|table| table.relid.load(Ordering::Acquire) == relid.as_u32())
+    }
+
+    /// The timers tables that statistics are kept for.
+    pub fn tables(&self) -> impl Iterator<Item = (Oid, &TableStats)> {
+        self.tables.iter().filter_map(|table| match table.relid.load(Ordering::Acquire) {
+            0 => None,
+            // Only OIDs of timers tables are ever stored.
+            relid => Some((unsafe { Oid::from_u32_unchecked(relid) }, table)),
+        })
+    }
+
+    /// Stop keeping statistics for a timers table, e.g. because it has been
+    /// dropped.
+    pub fn release_table(&self, relid: Oid) {
+        if let Some(table) = self.find_table(relid) {
+            table.reset();
+            table.relid.store(0, Ordering::Release);
+        }
+    }
+
+    /// Reset all statistics of the database.
+    pub fn reset(&self) {
+        self.timer_queue_overflows.reset();
+        self.worker_queue_overflows.reset();
+
+        for table in self.tables.iter() {
+            table.reset();
+            table.relid.store(0, Ordering::Release);
+        }
+    }
+}
+
+impl Default for DatabaseStats {
+    fn default() -> Self {
+        Self {
+            timer_queue_overflows: Counter::default(),
+            worker_queue_overflows: Counter::default(),
+            tables: std::array::from_fn(|_| TableStats::default()),
+        }
+    }
+}
+
+/// The statistics of a timers table.
+#[derive(Default)]
+pub struct TableStats {
+    // The OID of the table, or 0 if the statistics are unused.
+    relid: AtomicU32,
+
+    /// The number of timers that were created.
+    pub created: Counter,
+
+    /// The number of timers that fired, including redeliveries.
+    pub fired: Counter,
+
+    /// The number of failed attempts at firing timers.
+    pub failed: Counter,
+
+    /// The number of armed timers that were cancelled.
+    pub cancelled: Counter,
+
+    /// The number of misfired timers that were skipped.
+    pub missed: Counter,
+
+    // The histogram of the delays between the expiration of timers and their
+    // firing, with the buckets of `FIRE_LATENCY_BOUNDS_MS`.
+    fire_latency: [Counter; FIRE_LATENCY_BUCKETS],
+}
+
+impl TableStats {
+    /// Record the delay between the expiration of a timer and its firing.
+    pub fn record_fire_latency(&self, latency: chrono::Duration) {
+        let millis = latency.num_milliseconds();
+        let bucket = FIRE_LATENCY_BOUNDS_MS
+            .iter()
+            .position(|bound| millis < *bound)
+            .unwrap_or(FIRE_LATENCY_BOUNDS_MS.len());
+
+        self.fire_latency[bucket].incr();
+    }
+
+    /// The fire latency histogram.
+    pub fn fire_latency(&self) -> Vec<i64> {
+        self.fire_latency.iter().map(Counter::get).collect()
+    }
+
+    fn reset(&self) {
+        self.created.reset();
+        self.fired.reset();
+        self.failed.reset();
+        self.cancelled.reset();
+        self.missed.reset();
+
+        for bucket in self.fire_latency.iter() {
+            bucket.reset();
+        }
+    }
+}
@@ -0,0 +1,56 @@
+// src/subtransaction.rs
+
+//! Internal subtransactions for recovering from errors without aborting the
+//! surrounding transaction.
+
+use pgrx::pg_sys::panic::CaughtError;
+use pgrx::prelude::*;
+
+use std::panic::UnwindSafe;
+
+/// Run a function inside an internal subtransaction.
+///
+/// If the function raises an error, the subtransaction is rolled back and the
+/// error message is returned, leaving the surrounding transaction usable.
+pub fn run<F, R>(f: F) -> Result<R, String>
+where
+    F: FnOnce() -> R + UnwindSafe,
+{
+    let memory_context = unsafe { pg_sys::CurrentMemoryContext };
+    let resource_owner = unsafe { pg_sys::CurrentResourceOwner };
+
+    unsafe {
+        pg_sys::BeginInternalSubTransaction(std::ptr::null());
+        pg_sys::MemoryContextSwitchTo(memory_context);
+    }
+
+    PgTryBuilder::new(move || {
+        let result = f();
+
+        unsafe {
+            pg_sys::ReleaseCurrentSubTransaction();
+            pg_sys::MemoryContextSwitchTo(memory_context);
+            pg_sys::CurrentResourceOwner = resource_owner;
+        }
+
+        Ok(result)
+    })
+    .catch_others(move |e| {
+        let message = match e {
+            CaughtError::PostgresError(report) => report.message().to_string(),
+            CaughtError::ErrorReport(report) => report.message().to_string(),
+            CaughtError::RustPanic { ereport, .. } => ereport.message().to_string(),
+        };
+
+        unsafe {
+            pg_sys::MemoryContextSwitchTo(memory_context);
+            pg_sys::FlushErrorState();
+            pg_sys::RollbackAndReleaseCurrentSubTransaction();
+            pg_sys::MemoryContextSwitchTo(memory_context);
+            pg_sys::CurrentResourceOwner = resource_owner;
+        }
+
+        Err(message)
+    })
+    .execute()
+}
@@ -1,28 +1,36 @@
 // src/timer.rs
 
 use chrono::prelude::*;
-use heapless::mpmc::MpMcQueue;
+
+use heapless::Vec as HeaplessVec;
 
 use pgrx::bgworkers::*;
+use pgrx::callbacks::*;
 use pgrx::log;
+use pgrx::lwlock::PgLwLock;
 use pgrx::pg_shmem_init;
 use pgrx::pg_sys::Oid;
 use pgrx::prelude::*;
 use pgrx::shmem::*;
 
 use pgrx::spi::Error as SpiError;
-use tokio::task::AbortHandle;
-use tokio::time;
-use tokio::time::MissedTickBehavior;
-
+use std::cell::Cell;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::time::Duration as StdDuration;
+use std::time::Instant;
 
 use crate::commands;
 use crate::commands::TimerTableData;
 use crate::config;
+use crate::deadlines::Deadlines;
+use crate::launcher::DatabaseSlots;
+use crate::shmem::SharedLatch;
 use crate::shmem::SharedObject;
+use crate::shmem::SharedQueue;
+use crate::stats;
 use crate::types::*;
+use crate::workers;
 use crate::workers::TimerFiredEvent;
 use crate::workers::WorkerSubsystemEvent;
 use crate::workers::WorkersHandle;
@@ -31,26 +39,117 @@ use crate::workers::WorkersHandle;
 pub fn pg_init() {
     log!("quartz-timer: pg_init");
 
-    pg_shmem_init!(TIMER_EVENTS_QUEUE);
+    pg_shmem_init!(TIMER_EVENTS_QUEUES);
+    pg_shmem_init!(TIMER_LATCHES);
+    pg_shmem_init!(SCHEDULED_TIMERS_CHANNELS);
+}
 
-    BackgroundWorkerBuilder::new("quartz-timer")
+/// Start the timer subsystem of a database.
+///
+/// The subsystem exits right away if the extension is not installed in the
+/// database.
+pub fn start(database: &str) -> DynamicBackgroundWorker {
+    BackgroundWorkerBuilder::new(format!("quartz-timer ({})", database).as_str())
         .set_library("quartz")
         .set_function("quartz_timer_main")
         .set_argument(0.into_datum()) // can we use this for something?
+        .set_extra(database)
         .set_type("quartz-timer")
         .set_start_time(BgWorkerStartTime::RecoveryFinished)
-        .set_restart_time(StdDuration::from_secs(1).into())
+        .set_restart_time(config::restart_time())
+        .set_notify_pid(unsafe { pg_sys::MyProcPid })
         .enable_shmem_access(None)
         .enable_spi_access()
-        .load();
+        .load_dynamic()
 }
 
 /// The type of the queue of events that will be processed by the timer.
-type TimerEventsQueueType = MpMcQueue<TimerSubsystemEvent, 128>;
+type TimerEventsQueueType = SharedQueue<TimerSubsystemEvent, { config::MAX_QUEUE_SIZE }>;
+
+/// The queues of events that will be processed by the timer subsystems, one
+/// per database slot.
+static TIMER_EVENTS_QUEUES: SharedObject<[TimerEventsQueueType; config::MAX_DATABASES]> =
+    SharedObject::new("quartz-timer-events-queues");
+
+/// The latches of the timer subsystems, which are set when events are
+/// enqueued for them.
+static TIMER_LATCHES: SharedObject<[SharedLatch; config::MAX_DATABASES]> =
+    SharedObject::new("quartz-timer-latches");
+
+/// The number of armed timers that are handed to a backend at once.
+const SCHEDULED_TIMERS_PAGE_SIZE: usize = 1024;
+
+/// How long a backend waits for the timer subsystem to hand over armed
+/// timers.
+const SCHEDULED_TIMERS_TIMEOUT: StdDuration = StdDuration::from_secs(5);
+
+/// A timer that is armed in the timer subsystem.
+#[derive(Clone, Copy)]
+pub struct ScheduledTimer {
+    pub table_oid: Oid,
+    pub id: i64,
+    pub expires_at: DateTime<Local>,
+    /// The time at which the timer is handed to the workers, which is later
+    /// than its expiration if it is caught up after a misfire.
+    pub deadline: DateTime<Local>,
+}
+
+/// The state of a scheduled timers channel.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum ChannelState {
+    #[default]
+    Idle,
+    /// A backend uses the channel.
+    Claimed,
+    /// The backend waits for the timer subsystem to fill the page.
+    Requested,
+    /// The timer subsystem has filled the page.
+    Filled,
+}
+
+/// The channel through which a backend asks the timer subsystem of its
+/// database for the timers it has armed, one page at a time.
+#[derive(Default)]
+struct ScheduledTimersChannel {
+    state: ChannelState,
 
-/// The queue of events that will be processed by the timer subsystem.
-static TIMER_EVENTS_QUEUE: SharedObject<TimerEventsQueueType> =
-    SharedObject::new("quartz-timer-create-timer-queue");
+    // The process ID of the backend that uses the channel.
+    owner: i32,
+
+    // The latch of the backend that uses the channel, which is set once the
+    // page is filled.
+    requester: SharedLatch,
+
+    // The offset of the requested page into the timers.
+    offset: usize,
+
+    // The requested page, which is only partially filled if it is the last.
+    page: HeaplessVec<ScheduledTimer, SCHEDULED_TIMERS_PAGE_SIZE>,
+}
+
+/// The scheduled timers channels, one per database slot.
+#[derive(Default)]
+struct ScheduledTimersChannels([ScheduledTimersChannel; config::MAX_DATABASES]);
+
+unsafe impl PGRXSharedMemory for ScheduledTimersChannels {}
+
+static SCHEDULED_TIMERS_CHANNELS: PgLwLock<ScheduledTimersChannels> = PgLwLock::new();
+
+/// The interval at which the timer subsystem checks for the extension being
+/// dropped and for spilled events, while events are coming in.
+const MIN_POLL_INTERVAL: StdDuration = StdDuration::from_secs(1);
+
+/// The interval that the polls back off to while no events are coming in.
+const MAX_POLL_INTERVAL: StdDuration = StdDuration::from_secs(60);
+
+/// The maximum number of spilled events that are taken at once.
+const SPILLED_EVENTS_BATCH_SIZE: i64 = 1024;
+
+/// The number of attempts at enqueueing an event before giving up.
+const ENQUEUE_LOOPS: usize = 64;
+
+/// The delay before expiring timers again after the workers queue was full.
+const WORKERS_QUEUE_FULL_DELAY: StdDuration = StdDuration::from_millis(10);
 
 /// Events that can be consumed by the timer subsystem.
 pub enum TimerSubsystemEvent {
@@ -61,88 +160,511 @@ pub enum TimerSubsystemEvent {
         /// The row that was inserted into the table.
         table_row: CreateTimerFromRow,
     },
-    /// Process the expiration of a timer.
-    ExpireTimer {
+    /// Reschedule an existing timer.
+    RescheduleTimer {
         /// The OID of the table that the timer is associated with.
         table_oid: Oid,
-        /// The ID of the timer that should be expired.
+        /// The row that was updated in the table.
+        table_row: CreateTimerFromRow,
+    },
+    /// Cancel a pending timer.
+    CancelTimer {
+        /// The OID of the table that the timer is associated with.
+        table_oid: Oid,
+        /// The ID of the timer that should be cancelled.
         timer_id: i64,
     },
     /// Track a new timers table.
     TrackTimersTable {
         /// The OID of the table that should be tracked.
         table_oid: Oid,
+        /// The misfire policy of the table.
+        misfire_policy: MisfirePolicy,
     },
     /// Untrack a timers table.
     UntrackTimersTable {
         /// The OID of the table that should be untracked.
         table_oid: Oid,
     },
+    /// Change the misfire policy of a timers table.
+    UpdateMisfirePolicy {
+        /// The OID of the table whose policy changed.
+        table_oid: Oid,
+        /// The new misfire policy of the table.
+        misfire_policy: MisfirePolicy,
+    },
+}
+
+impl TimerSubsystemEvent {
+    /// The table that the event refers to, and the timer if the event is not
+    /// about the table as a whole.
+    fn target(&self) -> (Oid, Option<i64>) {
+        match self {
+            Self::CreateTimer {
+                table_oid,
+                table_row,
+            }
+            | Self::RescheduleTimer {
+                table_oid,
+                table_row,
+            } => (*table_oid, Some(table_row.id)),
+            Self::CancelTimer {
+                table_oid,
+                timer_id,
+            } => (*table_oid, Some(*timer_id)),
+            Self::TrackTimersTable { table_oid, .. }
+            | Self::UntrackTimersTable { table_oid }
+            | Self::UpdateMisfirePolicy { table_oid, .. } => (*table_oid, None),
+        }
+    }
+}
+
+/// The state of a table or timer that an event was spilled for, as of when
+/// the timer subsystem takes the event.
+enum SpilledEvent {
+    /// A timers table, and its misfire policy if it still exists.
+    Table {
+        table_oid: Oid,
+        misfire_policy: Option<MisfirePolicy>,
+    },
+    /// A timer, and its row along with the misfire policy of its table if it
+    /// is still pending.
+    Timer {
+        table_oid: Oid,
+        timer_id: i64,
+        row: Option<(CreateTimerFromRow, MisfirePolicy)>,
+    },
 }
 
-/// The timer handle is a handle for interacting with the timer subsystem.
+/// The timer handle is a handle for interacting with the timer subsystem of
+/// the current database.
 ///
 /// TimerHandle is not usable prior to the initialization of the timer
 /// subsystem.
 #[derive(Clone, Copy)]
-pub struct TimerHandle(());
+pub struct TimerHandle {
+    // The database slot of the subsystem, if one could be claimed.
+    slot: Option<usize>,
+}
+
+thread_local! {
+    /// Whether this backend has warned that its database has no timer
+    /// subsystem.
+    static NO_SUBSYSTEM_WARNED: Cell<bool> = const { Cell::new(false) };
+}
 
 impl TimerHandle {
     /// Create a new timer handle.
+    ///
+    /// If the database has no timer subsystem, because all database slots are
+    /// taken, the handle cannot enqueue events. The backend warns about it
+    /// once.
     pub fn get() -> Self {
-        Self(())
+        let database = unsafe { pg_sys::MyDatabaseId };
+        let slot = DatabaseSlots::get().claim(database);
+
+        if slot.is_none() && !NO_SUBSYSTEM_WARNED.with(|warned| warned.replace(true)) {
+            warning!(
+                "quartz: database {} has no timer subsystem: at most {} databases are supported",
+                database,
+                config::MAX_DATABASES
+            );
+        }
+
+        Self { slot }
     }
 
-    /// Enqueue an event to be processed by the timer subsystem.
+    /// Enqueue an event to be processed by the timer subsystem once the
+    /// current transaction commits.
+    ///
+    /// Events of a transaction or savepoint that is rolled back are discarded,
+    /// so that the timer subsystem never sees rows that do not exist.
     ///
-    /// This function will loop a few times if the queue is full, to give the
-    /// background worker a chance to dequeue some events.
+    /// Room for the event is reserved in the queue right away. If the queue is
+    /// full, the event is spilled to the `quartz.spilled_events` table
+    /// instead, which the timer subsystem drains. Spilled events are part of
+    /// the transaction, so they are discarded on rollback as well.
+    ///
+    /// Returns false if the database has no timer subsystem.
+    pub fn enqueue_event_on_commit(&self, event: TimerSubsystemEvent) -> bool {
+        let slot = if let Some(value) = self.slot {
+            value
+        } else {
+            return false;
+        };
+
+        let queue = &TIMER_EVENTS_QUEUES.get()[slot];
+        let subtransaction_id = unsafe { pg_sys::GetCurrentSubTransactionId() };
+
+        PENDING_EVENTS.with(|pending| {
+            let mut pending = pending.borrow_mut();
+
+            if !pending.registered {
+                register_pending_events_callbacks();
+                pending.registered = true;
+            }
+
+            // Created timers are counted once they are committed, whether
+            // their event is queued or spilled.
+            if let TimerSubsystemEvent::CreateTimer { table_oid, .. } = &event {
+                pending.created.push((subtransaction_id, *table_oid));
+            }
+        });
+
+        if !queue.reserve(config::TIMER_QUEUE_SIZE.get() as usize) {
+            return Self::spill_event(slot, event);
+        }
+
+        PENDING_EVENTS.with(|pending| {
+            pending.borrow_mut().events.push((subtransaction_id, event));
+        });
+
+        true
+    }
+
+    /// Write an event that does not fit into the queue to the spill table.
+    fn spill_event(slot: usize, event: TimerSubsystemEvent) -> bool {
+        let (table_oid, timer_id) = event.target();
+
+        let result = Spi::connect(|mut client| {
+            commands::spill_timer_event(&mut client, table_oid, timer_id)
+        });
+
+        stats::database(slot).timer_queue_overflows.incr();
+
+        if let Err(e) = result {
+            warning!("quartz: failed to spill timer event: {}", e);
+
+            return false;
+        }
+
+        DatabaseSlots::get().mark_pending(slot);
+
+        true
+    }
+
+    /// Ask the timer subsystem for the timers it has armed, ordered by the
+    /// time at which they are handed to the workers.
     ///
-    /// Returns the ID of the timer if the event was successfully enqueued.
-    pub fn enqueue_event(&self, mut event: TimerSubsystemEvent) -> bool {
-        const LOOPS: usize = 64;
-
-        for _ in 0..LOOPS {
-            event = match TIMER_EVENTS_QUEUE.get().enqueue(event) {
-                Ok(_) => return true,
-                Err(value) => value,
+    /// Raises an error if the timer subsystem does not respond in time, e.g.
+    /// because it is not running.
+    pub fn scheduled_timers(&self) -> Vec<ScheduledTimer> {
+        let slot = if let Some(value) = self.slot {
+            value
+        } else {
+            error!("quartz: database has no timer subsystem");
+        };
+
+        let deadline = Instant::now() + SCHEDULED_TIMERS_TIMEOUT;
+
+        // Another backend may be using the channel. It is taken over if that
+        // backend has exited without releasing it.
+        while !Self::claim_channel(slot) {
+            Self::wait_for_channel(deadline, "another backend is inspecting the timers");
+        }
+
+        let _guard = ChannelGuard(slot);
+
+        let mut timers = Vec::new();
+
+        loop {
+            {
+                let channels = &mut SCHEDULED_TIMERS_CHANNELS.exclusive().0;
+                let channel = &mut channels[slot];
+
+                channel.offset = timers.len();
+                channel.state = ChannelState::Requested;
+            }
+
+            TIMER_LATCHES.get()[slot].set();
+
+            let len = loop {
+                {
+                    let channels = &SCHEDULED_TIMERS_CHANNELS.share().0;
+                    let channel = &channels[slot];
+
+                    if channel.state == ChannelState::Filled {
+                        timers.extend_from_slice(&channel.page);
+
+                        break channel.page.len();
+                    }
+                }
+
+                Self::wait_for_channel(deadline, "the timer subsystem did not respond");
             };
+
+            if len < SCHEDULED_TIMERS_PAGE_SIZE {
+                return timers;
+            }
+        }
+    }
+
+    /// Claim the scheduled timers channel of a slot for this backend.
+    fn claim_channel(slot: usize) -> bool {
+        let channels = &mut SCHEDULED_TIMERS_CHANNELS.exclusive().0;
+        let channel = &mut channels[slot];
+
+        let abandoned = channel.state != ChannelState::Idle
+            && unsafe { pg_sys::BackendPidGetProc(channel.owner) }.is_null();
+
+        if channel.state != ChannelState::Idle && !abandoned {
+            return false;
+        }
+
+        channel.state = ChannelState::Claimed;
+        channel.owner = unsafe { pg_sys::MyProcPid };
+        channel.requester.attach();
+
+        true
+    }
+
+    /// Sleep on the latch of this backend until it is set, raising an error
+    /// if the deadline has passed.
+    fn wait_for_channel(deadline: Instant, message: &str) {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+
+        if timeout.is_zero() {
+            error!("quartz: {}", message);
+        }
+
+        unsafe {
+            pg_sys::WaitLatch(
+                pg_sys::MyLatch,
+                (pg_sys::WL_LATCH_SET | pg_sys::WL_TIMEOUT | pg_sys::WL_EXIT_ON_PM_DEATH) as i32,
+                timeout.as_millis().clamp(1, 100) as i64,
+                pg_sys::PG_WAIT_EXTENSION,
+            );
+            pg_sys::ResetLatch(pg_sys::MyLatch);
+        }
+
+        check_for_interrupts!();
+    }
+
+    /// The number of events in the queue of the timer subsystem, if the
+    /// database has one.
+    pub fn queue_depth(&self) -> Option<usize> {
+        self.queue().map(|queue| queue.depth())
+    }
+
+    /// The queue of the timer subsystem, if the database has one.
+    fn queue(&self) -> Option<&'static TimerEventsQueueType> {
+        self.slot.map(|slot| &TIMER_EVENTS_QUEUES.get()[slot])
+    }
+}
+
+/// Releases the scheduled timers channel of a slot when dropped, including
+/// when an error is raised while waiting on it.
+struct ChannelGuard(usize);
+
+impl Drop for ChannelGuard {
+    fn drop(&mut self) {
+        let channels = &mut SCHEDULED_TIMERS_CHANNELS.exclusive().0;
+        let channel = &mut channels[self.0];
+
+        if channel.owner == unsafe { pg_sys::MyProcPid } {
+            channel.requester.detach();
+            channel.state = ChannelState::Idle;
+            channel.owner = 0;
+            channel.page.clear();
+        }
+    }
+}
+
+/// The events enqueued in the current transaction, which are handed to the
+/// timer subsystem on commit.
+#[derive(Default)]
+struct PendingEvents {
+    // The events, along with the ID of the subtransaction they were enqueued
+    // in. Each of them holds a reservation in the queue.
+    events: Vec<(pg_sys::SubTransactionId, TimerSubsystemEvent)>,
+
+    // The tables of the timers created in the transaction, along with the ID
+    // of the subtransaction they were created in.
+    created: Vec<(pg_sys::SubTransactionId, Oid)>,
+
+    // Whether the transaction callbacks are registered. They only last for a
+    // single transaction.
+    registered: bool,
+}
+
+thread_local! {
+    /// The pending events of the current transaction in this backend.
+    static PENDING_EVENTS: RefCell<PendingEvents> = RefCell::new(PendingEvents::default());
+}
+
+/// Register the callbacks that settle the pending events of the current
+/// transaction.
+fn register_pending_events_callbacks() {
+    register_xact_callback(PgXactCallbackEvent::Commit, || {
+        let PendingEvents {
+            events, created, ..
+        } = take_pending_events();
+
+        let timer_handle = TimerHandle::get();
+
+        if let Some(slot) = timer_handle.slot {
+            let stats = stats::database(slot);
+
+            for (_, table_oid) in created {
+                if let Some(table) = stats.table(table_oid) {
+                    table.created.incr();
+                }
+            }
+        }
+
+        if events.is_empty() {
+            return;
+        }
+
+        let queue = if let Some(value) = timer_handle.queue() {
+            value
+        } else {
+            return;
+        };
+
+        // The transaction has already committed, so failures can no longer
+        // be raised as errors. Since room has been reserved for the events,
+        // enqueueing them only fails in exceptional cases.
+        let mut failed = 0;
+
+        for (_, mut event) in events {
+            let mut enqueued = false;
+
+            for _ in 0..ENQUEUE_LOOPS {
+                event = match queue.enqueue_reserved(event) {
+                    Ok(_) => {
+                        enqueued = true;
+                        break;
+                    }
+                    Err(value) => value,
+                };
+            }
+
+            if !enqueued {
+                queue.release();
+                failed += 1;
+            }
+        }
+
+        if let Some(slot) = timer_handle.slot {
+            DatabaseSlots::get().mark_pending(slot);
+            TIMER_LATCHES.get()[slot].set();
+        }
+
+        if failed > 0 {
+            warning!("quartz: failed to enqueue {} timer events on commit", failed);
         }
+    });
 
-        false
+    register_xact_callback(PgXactCallbackEvent::Abort, || {
+        release_reservations(take_pending_events().events.len());
+    });
+
+    // The timer subsystem can not be told about events that are committed
+    // later by another backend.
+    register_xact_callback(PgXactCallbackEvent::PrePrepare, || {
+        if PENDING_EVENTS.with(|pending| !pending.borrow().events.is_empty()) {
+            error!("cannot PREPARE a transaction that has changed timers");
+        }
+    });
+
+    // Subtransaction IDs are assigned in increasing order, so the events of a
+    // subtransaction that is rolled back, and of the subtransactions nested
+    // in it, are the ones with IDs at least as high as its own.
+    register_subxact_callback(PgSubXactCallbackEvent::AbortSub, |subtransaction_id, _| {
+        let discarded = PENDING_EVENTS.with(|pending| {
+            let mut pending = pending.borrow_mut();
+            let len = pending.events.len();
+
+            pending.events.retain(|(id, _)| *id < subtransaction_id);
+            pending.created.retain(|(id, _)| *id < subtransaction_id);
+
+            len - pending.events.len()
+        });
+
+        release_reservations(discarded);
+    });
+}
+
+/// Give back the reservations of discarded pending events.
+fn release_reservations(count: usize) {
+    if count == 0 {
+        return;
+    }
+
+    if let Some(queue) = TimerHandle::get().queue() {
+        for _ in 0..count {
+            queue.release();
+        }
     }
 }
 
-/// Main function of the timer subsystem.
+/// Take the pending events of the current transaction, which is about to end.
+fn take_pending_events() -> PendingEvents {
+    PENDING_EVENTS.with(|pending| std::mem::take(&mut *pending.borrow_mut()))
+}
+
+/// The PID of the timer subsystem of a database slot, if it is running.
+pub(crate) fn timer_pid(slot: usize) -> Option<i32> {
+    TIMER_LATCHES.get()[slot].owner_pid()
+}
+
+/// Main function of the timer subsystem of a database.
 ///
-/// This sets up the BackgroundWorker, the tokio runtime and then creates a new
-/// Timer instance and blocks on it's run method.
+/// This sets up the BackgroundWorker, creates a new Timer instance, starts the
+/// workers and then blocks on the timer's run method.
 #[pg_guard]
 #[no_mangle]
 pub extern "C" fn quartz_timer_main(_arg: pg_sys::Datum) {
-    log!("quartz-timer: starting");
+    let database = BackgroundWorker::get_extra();
+
+    log!("quartz-timer: starting for database \"{}\"", database);
 
     BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGHUP | SignalWakeFlags::SIGTERM);
-    BackgroundWorker::connect_worker_to_spi(config::SPI_DATABASE_NAME, config::SPI_USER_NAME);
+    BackgroundWorker::connect_worker_to_spi(Some(database), config::role().as_deref());
 
-    let mut timer = Timer::new(TIMER_EVENTS_QUEUE.get());
+    match Timer::is_extension_installed() {
+        Ok(true) => {}
+        Ok(false) => {
+            log!("quartz-timer: extension is not installed in database \"{}\"", database);
 
-    if let Err(e) = timer.initialize_extension() {
-        error!("quartz-timer: failed to initialize schema: {}", e);
+            // Databases without a slot were only probed, the launcher has no
+            // record of them.
+            if let Some(slot) = DatabaseSlots::get().find(unsafe { pg_sys::MyDatabaseId }) {
+                DatabaseSlots::get().report_extension(slot, false);
+            }
+
+            return;
+        }
+        Err(e) => error!("quartz-timer: failed to check for the extension: {}", e),
     }
 
+    let slot = if let Some(value) = TimerHandle::get().slot {
+        value
+    } else {
+        return;
+    };
+
+    DatabaseSlots::get().report_extension(slot, true);
+
+    let latch = &TIMER_LATCHES.get()[slot];
+    latch.attach();
+
+    let mut timer = Timer::new(slot);
+
     if let Err(e) = timer.initialize() {
         error!("quartz-timer: failed to initialize: {}", e);
     }
 
-    let runtime = tokio::runtime::Builder::new_current_thread()
-        .enable_time()
-        .event_interval(61) // default - might want to optimize
-        .max_blocking_threads(1)
-        .build()
-        .unwrap();
+    // The workers are only started once the subsystem is initialized. They
+    // are terminated when it exits, also on errors.
+    let workers = workers::start(database);
+
+    timer.run();
+
+    latch.detach();
 
-    runtime.block_on(timer.run());
+    drop(workers);
 
     log!("quartz-timer: bye bye");
 }
@@ -152,136 +674,345 @@ pub extern "C" fn quartz_timer_main(_arg: pg_sys::Datum) {
 struct TimerEntry {
     oid: Oid,
     row: TimerRow,
-    handle: AbortHandle,
+
+    // Whether the timer misfired, i.e. was armed long after its expiration.
+    misfired: bool,
+}
+
+/// The misfire handling of a timers table.
+struct Misfires {
+    policy: MisfirePolicy,
+
+    // The earliest time at which the next misfired timer may fire, if
+    // misfired timers are caught up at a limited rate.
+    next_catch_up: DateTime<Local>,
 }
 
+/// The key of a timer in the deadlines, as the table's OID and the row's ID.
+type TimerKey = (u32, i64);
+
 /// The timer subsystem.
+///
+/// The deadlines of all armed timers are kept in a single min-heap, and the
+/// subsystem only ever sleeps until the earliest of them.
 struct Timer {
+    slot: usize,
     queue: &'static TimerEventsQueueType,
-    timer_handle: TimerHandle,
     timers: HashMap<Oid, HashMap<i64, TimerEntry>>,
+    misfires: HashMap<Oid, Misfires>,
+    deadlines: Deadlines<TimerKey>,
     workers_handle: WorkersHandle,
+
+    // No timers are expired before this time, since the workers queue was
+    // full.
+    expire_after: Option<DateTime<Local>>,
+
+    // The armed timers as of the first page that a backend requested, from
+    // which the following pages are served.
+    scheduled_timers: Vec<ScheduledTimer>,
 }
 
 impl Timer {
-    /// Create a new timer subsystem.
-    fn new(queue: &'static TimerEventsQueueType) -> Self {
+    /// Create a new timer subsystem for a database slot.
+    fn new(slot: usize) -> Self {
         Self {
-            queue,
-            timer_handle: TimerHandle::get(),
+            slot,
+            queue: &TIMER_EVENTS_QUEUES.get()[slot],
             timers: HashMap::new(),
+            misfires: HashMap::new(),
+            deadlines: Deadlines::default(),
             workers_handle: WorkersHandle::get(),
+            expire_after: None,
+            scheduled_timers: Vec::new(),
         }
     }
 
-    /// Initialize the schema for the timer subsystem.
-    fn initialize_extension(&mut self) -> Result<(), SpiError> {
+    /// Check whether the extension is (still) installed in the database.
+    fn is_extension_installed() -> Result<bool, SpiError> {
         BackgroundWorker::transaction(|| {
-            Spi::connect(|mut client| {
-                client.update("create extension if not exists quartz", None, None)
-            })
-            .map(|_| ())
+            Spi::connect(|client| commands::is_extension_installed(&client))
         })
     }
 
+    /// Load the timers of all timers tables.
+    ///
+    /// Pending timers are armed with their expiration. Timers that have fired
+    /// but have not been completed, e.g. because the server crashed before
+    /// the application could complete them, are fired again right away if
+    /// the policy of their table asks for it.
     fn initialize(&mut self) -> Result<(), SpiError> {
-        let timer_handle = self.timer_handle.clone();
+        let result: Result<Vec<(TimerTableData, Vec<TimerRow>)>, SpiError> =
+            BackgroundWorker::transaction(|| {
+                Spi::connect(|client| {
+                    // Deactivated tables are not tracked.
+                    let timer_tables = commands::find_timer_tables(&client)?
+                        .into_iter()
+                        .filter(|timer_table| timer_table.enabled)
+                        .collect::<Vec<_>>();
+
+                    log!("quartz-timer: found {} timer tables", timer_tables.len());
+
+                    let mut vec = Vec::with_capacity(timer_tables.len());
+
+                    for timer_table in timer_tables {
+                        let timers = commands::find_timers_in_table(
+                            &client,
+                            timer_table.schema.as_str(),
+                            timer_table.table.as_str(),
+                            &timer_table.columns,
+                        )?;
+
+                        log!(
+                            "quartz-timer: found {} timers in table {}",
+                            timers.len(),
+                            timer_table.table
+                        );
+
+                        vec.push((timer_table, timers));
+                    }
 
-        BackgroundWorker::transaction(|| {
-            let timer_tables = Spi::connect(|client| commands::find_timer_tables(&client))?;
+                    Ok(vec)
+                })
+            });
 
-            warning!("quartz-timer: found {} timer tables", timer_tables.len());
+        for (timer_table, timers) in result? {
+            let TimerTableData {
+                relid,
+                retry_policy,
+                misfire_policy,
+                ..
+            } = timer_table;
+
+            self.track_timers_table(relid, misfire_policy);
+
+            for timer in timers {
+                // Timers that have exhausted their retries or were missed are
+                // left alone.
+                if timer.attempts >= retry_policy.max_attempts || timer.missed_at.is_some() {
+                    continue;
+                }
 
-            // fixme: this solution can potentially fail if there are too
-            // many timers that need loading, because the queue is quite small.
-            for timer_table in timer_tables {
-                let TimerTableData {
-                    relid,
-                    schema,
-                    table,
-                } = timer_table;
+                // Redeliveries are not subject to the misfire policy, since
+                // the timers have fired already.
+                let redelivery = match (timer.fired_at, timer.completed_at) {
+                    (None, _) => false,
+                    (Some(_), None) if retry_policy.redeliver => {
+                        config::log_activity!(
+                            "quartz-timer: timer {} has not been completed, redelivering",
+                            TimerId::new(relid, timer.id)
+                        );
+
+                        true
+                    }
+                    _ => continue,
+                };
 
-                if !timer_handle
-                    .enqueue_event(TimerSubsystemEvent::TrackTimersTable { table_oid: relid })
-                {
-                    error!("failed to enqueue event");
-                }
+                self.create_timer(relid, timer, redelivery);
+            }
+        }
 
-                let timers = Spi::connect(|client| {
-                    commands::find_timers_in_table(&client, schema.as_str(), table.as_str())
-                })?;
+        Ok(())
+    }
 
-                warning!(
-                    "quartz-timer: found {} timers in table {}",
-                    timers.len(),
-                    table
-                );
+    /// Run the timer subsystem.
+    ///
+    /// The subsystem sleeps on its latch until the next timer is due, unless
+    /// it is woken up earlier by enqueued events.
+    fn run(&mut self) {
+        let mut poll_interval = MIN_POLL_INTERVAL;
+        let mut next_poll = Instant::now();
 
-                for timer in timers {
-                    if timer.fired_at.is_some() {
-                        continue;
-                    }
+        loop {
+            let poll = Instant::now() >= next_poll;
 
-                    if !timer_handle.enqueue_event(TimerSubsystemEvent::CreateTimer {
-                        table_oid: relid,
-                        table_row: timer.into(),
-                    }) {
-                        error!("failed to enqueue event");
-                    }
+            let spilled = if poll {
+                match self.on_poll_interval() {
+                    Some(value) => value,
+                    None => break,
                 }
+            } else {
+                Vec::new()
+            };
+
+            let processed = match self.process_events(spilled) {
+                Some(value) => value,
+                None => break,
+            };
+
+            // Polls back off while the subsystem is idle. Events are usually
+            // spilled while the queue is busy, so they are taken soon.
+            if processed > 0 {
+                poll_interval = MIN_POLL_INTERVAL;
+                next_poll = next_poll.min(Instant::now() + poll_interval);
+            } else if poll {
+                poll_interval = (poll_interval * 2).min(MAX_POLL_INTERVAL);
             }
 
-            Ok(())
-        })
-    }
+            if poll {
+                next_poll = Instant::now() + poll_interval;
+            }
 
-    /// Run the timer subsystem.
-    async fn run(&mut self) {
-        let mut poll_term_interval = time::interval(StdDuration::from_secs(1));
-        poll_term_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            self.expire_due_timers();
+            self.serve_scheduled_timers();
 
-        let mut poll_timers_interval = time::interval(StdDuration::from_millis(1));
-        poll_timers_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            let until_poll = next_poll.saturating_duration_since(Instant::now());
+            let timeout = match self.next_timeout() {
+                Some(value) => value.min(until_poll),
+                None => until_poll,
+            };
 
-        let on_poll_term_interval = || {
-            if BackgroundWorker::sigterm_received() {
-                return false;
+            if !BackgroundWorker::wait_latch(Some(timeout)) {
+                break;
             }
+        }
+    }
 
-            if BackgroundWorker::sighup_received() {
-                // on SIGHUP, you might want to reload some external configuration or something
+    /// Take the spilled events, unless the subsystem has to stop.
+    fn on_poll_interval(&self) -> Option<Vec<SpilledEvent>> {
+        if BackgroundWorker::sigterm_received() {
+            return None;
+        }
+
+        if BackgroundWorker::sighup_received() {
+            // on SIGHUP, you might want to reload some external configuration or something
+        }
+
+        match Self::take_spilled_events() {
+            Ok(Some(events)) => Some(events),
+            // The subsystem stops when the extension is dropped. Queued events
+            // refer to tables that no longer exist, so they are discarded.
+            Ok(None) => {
+                log!("quartz-timer: extension has been dropped");
+
+                while self.queue.dequeue().is_some() {}
+
+                DatabaseSlots::get().report_extension(self.slot, false);
+
+                None
             }
+            Err(e) => {
+                warning!("quartz-timer: failed to take spilled events: {}", e);
 
-            true
-        };
+                Some(Vec::new())
+            }
+        }
+    }
 
-        let mut on_poll_timers_interval = || {
-            loop {
-                match self.queue.dequeue() {
-                    Some(event) => {
-                        if !self.process_event(event) {
-                            return false;
-                        }
-                    }
-                    None => break,
-                };
+    /// Process all queued events, and then the spilled ones.
+    ///
+    /// Returns the number of events processed, or None if the subsystem has to
+    /// stop.
+    fn process_events(&mut self, spilled: Vec<SpilledEvent>) -> Option<usize> {
+        let mut processed = 0;
+
+        while let Some(event) = self.queue.dequeue() {
+            if !self.process_event(event) {
+                return None;
             }
 
-            true
+            processed += 1;
+        }
+
+        // Spilled events are processed after the queue has been drained, since
+        // they have usually been enqueued later.
+        processed += spilled.len();
+
+        for event in spilled {
+            self.process_spilled_event(event);
+        }
+
+        Some(processed)
+    }
+
+    /// Hand a page of the armed timers to the backend that requested it, if
+    /// any.
+    fn serve_scheduled_timers(&mut self) {
+        // Requests are rare, so the channel is only locked exclusively if
+        // there is one.
+        if SCHEDULED_TIMERS_CHANNELS.share().0[self.slot].state != ChannelState::Requested {
+            return;
+        }
+
+        let channels = &mut SCHEDULED_TIMERS_CHANNELS.exclusive().0;
+        let channel = &mut channels[self.slot];
+
+        if channel.state != ChannelState::Requested {
+            return;
+        }
+
+        // The timers are collected once per request, so that the pages are
+        // consistent with each other.
+        if channel.offset == 0 {
+            self.scheduled_timers = self
+                .deadlines
+                .iter()
+                .filter_map(|(&(oid, id), &deadline)| {
+                    // The deadlines only hold keys of tracked timers.
+                    let table_oid = unsafe { Oid::from_u32_unchecked(oid) };
+                    let entry = self.timers.get(&table_oid)?.get(&id)?;
+
+                    Some(ScheduledTimer {
+                        table_oid,
+                        id,
+                        expires_at: entry.row.expires_at,
+                        deadline,
+                    })
+                })
+                .collect();
+
+            self.scheduled_timers
+                .sort_unstable_by_key(|timer| (timer.deadline, timer.table_oid.as_u32(), timer.id));
+        }
+
+        let page = self
+            .scheduled_timers
+            .iter()
+            .skip(channel.offset)
+            .take(SCHEDULED_TIMERS_PAGE_SIZE);
+
+        channel.page.clear();
+
+        for timer in page {
+            // The page has room for a full page of timers.
+            let _ = channel.page.push(*timer);
+        }
+
+        if channel.page.len() < SCHEDULED_TIMERS_PAGE_SIZE {
+            self.scheduled_timers = Vec::new();
+        }
+
+        channel.state = ChannelState::Filled;
+        channel.requester.set();
+    }
+
+    /// The time until the next timer has to be expired, if any.
+    fn next_timeout(&mut self) -> Option<StdDuration> {
+        let deadline = match (self.deadlines.next()?, self.expire_after) {
+            (deadline, Some(after)) if after > deadline => after,
+            (deadline, _) => deadline,
         };
 
-        loop {
-            tokio::select! {
-                _ = poll_term_interval.tick() => {
-                    if !on_poll_term_interval() {
-                        break;
-                    }
-                }
-                _ = poll_timers_interval.tick() => {
-                    if !on_poll_timers_interval() {
-                        break;
-                    }
-                }
+        Some((deadline - Local::now()).to_std().unwrap_or_default())
+    }
+
+    /// Expire all timers that are due.
+    fn expire_due_timers(&mut self) {
+        let now = Local::now();
+
+        if matches!(self.expire_after, Some(after) if after > now) {
+            return;
+        }
+
+        self.expire_after = None;
+
+        while let Some((key, deadline)) = self.deadlines.pop_due(now) {
+            if !self.expire_timer(key) {
+                // The workers queue is full, so the timer is expired again
+                // once the workers have caught up a bit.
+                self.deadlines.insert(key, deadline);
+                self.expire_after = Some(now + chrono::Duration::from_std(WORKERS_QUEUE_FULL_DELAY).unwrap());
+
+                break;
             }
         }
     }
@@ -292,33 +1023,153 @@ impl Timer {
                 table_oid,
                 table_row,
             } => {
-                self.create_timer(table_oid, table_row);
+                self.create_timer(table_oid, table_row.into(), false);
+            }
+            TimerSubsystemEvent::RescheduleTimer {
+                table_oid,
+                table_row,
+            } => {
+                self.reschedule_timer(table_oid, table_row);
             }
-            TimerSubsystemEvent::ExpireTimer {
+            TimerSubsystemEvent::CancelTimer {
                 table_oid,
                 timer_id,
             } => {
-                self.expire_timer(table_oid, timer_id);
+                self.cancel_timer(table_oid, timer_id);
             }
-            TimerSubsystemEvent::TrackTimersTable { table_oid } => {
-                self.track_timers_table(table_oid);
+            TimerSubsystemEvent::TrackTimersTable {
+                table_oid,
+                misfire_policy,
+            } => {
+                self.track_timers_table(table_oid, misfire_policy);
             }
             TimerSubsystemEvent::UntrackTimersTable { table_oid } => {
                 self.untrack_timers_table(table_oid);
             }
+            TimerSubsystemEvent::UpdateMisfirePolicy {
+                table_oid,
+                misfire_policy,
+            } => {
+                self.update_misfire_policy(table_oid, misfire_policy);
+            }
         }
 
         true
     }
 
-    fn create_timer(&mut self, table_oid: Oid, row: CreateTimerFromRow) {
+    /// Take the spilled events, along with the current state of the tables
+    /// and timers they refer to.
+    ///
+    /// Returns None if the extension has been dropped.
+    fn take_spilled_events() -> Result<Option<Vec<SpilledEvent>>, SpiError> {
+        BackgroundWorker::transaction(|| {
+            Spi::connect(|mut client| {
+                if !commands::is_extension_installed(&client)? {
+                    return Ok(None);
+                }
+
+                let events = commands::take_spilled_events(&mut client, SPILLED_EVENTS_BATCH_SIZE)?;
+
+                let mut vec = Vec::with_capacity(events.len());
+
+                for (table_oid, timer_id) in events {
+                    // Deactivated tables are treated like dropped ones.
+                    let table = commands::find_timer_table(&client, table_oid)?
+                        .filter(|table| table.enabled);
+
+                    let event = match (timer_id, table) {
+                        (None, table) => SpilledEvent::Table {
+                            table_oid,
+                            misfire_policy: table.map(|table| table.misfire_policy),
+                        },
+                        (Some(timer_id), None) => SpilledEvent::Timer {
+                            table_oid,
+                            timer_id,
+                            row: None,
+                        },
+                        (Some(timer_id), Some(table)) => {
+                            let row = commands::find_timer(
+                                &client,
+                                table.schema.as_str(),
+                                table.table.as_str(),
+                                &table.columns,
+                                timer_id,
+                            )?
+                            .filter(|row| row.fired_at.is_none() && row.missed_at.is_none())
+                            .filter(|row| row.attempts < table.retry_policy.max_attempts)
+                            .map(|row| (CreateTimerFromRow::from(row), table.misfire_policy));
+
+                            SpilledEvent::Timer {
+                                table_oid,
+                                timer_id,
+                                row,
+                            }
+                        }
+                    };
+
+                    vec.push(event);
+                }
+
+                Ok(Some(vec))
+            })
+        })
+    }
+
+    /// Bring a table or timer that an event was spilled for up to date.
+    ///
+    /// Spilled events may be processed out of order with queued ones, so
+    /// they are applied according to the current state instead of the change
+    /// that caused them.
+    fn process_spilled_event(&mut self, event: SpilledEvent) {
+        match event {
+            SpilledEvent::Table {
+                table_oid,
+                misfire_policy,
+            } => {
+                let tracked = self.timers.contains_key(&table_oid);
+
+                match misfire_policy {
+                    Some(policy) if tracked => self.update_misfire_policy(table_oid, policy),
+                    Some(policy) => self.track_timers_table(table_oid, policy),
+                    None if tracked => self.untrack_timers_table(table_oid),
+                    None => {}
+                }
+            }
+            SpilledEvent::Timer {
+                table_oid,
+                timer_id,
+                row,
+            } => {
+                // The outcome of a timer that is being fired is up to the
+                // worker firing it.
+                if self.workers_handle.is_timer_firing(table_oid, timer_id) {
+                    return;
+                }
+
+                if let Some((row, misfire_policy)) = row {
+                    if !self.timers.contains_key(&table_oid) {
+                        self.track_timers_table(table_oid, misfire_policy);
+                    }
+
+                    self.reschedule_timer(table_oid, row);
+                } else if self
+                    .timers
+                    .get(&table_oid)
+                    .is_some_and(|timers| timers.contains_key(&timer_id))
+                {
+                    self.cancel_timer(table_oid, timer_id);
+                }
+            }
+        }
+    }
+
+    fn create_timer(&mut self, table_oid: Oid, row: TimerRow, redelivery: bool) {
         let scoped_timers = if let Some(value) = self.timers.get_mut(&table_oid) {
             value
         } else {
             warning!(
-                "quartz-timer: failed to create timer {} ({}): table is not tracked",
-                row.id,
-                table_oid
+                "quartz-timer: failed to create timer {}: table is not tracked",
+                TimerId::new(table_oid, row.id)
             );
 
             return;
@@ -326,76 +1177,195 @@ impl Timer {
 
         if scoped_timers.contains_key(&row.id) {
             warning!(
-                "quartz-timer: timer {} ({}) is already tracked",
-                row.id,
-                table_oid
+                "quartz-timer: timer {} is already tracked",
+                TimerId::new(table_oid, row.id)
             );
 
             return;
         }
 
-        let timer_handle = self.timer_handle;
+        let (id, expires_at) = (row.id, row.expires_at);
+
+        scoped_timers.insert(
+            row.id,
+            TimerEntry {
+                oid: table_oid,
+                row,
+                misfired: false,
+            },
+        );
+
+        self.arm_timer(table_oid, id, expires_at, redelivery);
+    }
+
+    fn reschedule_timer(&mut self, table_oid: Oid, row: CreateTimerFromRow) {
+        let scoped_timers = if let Some(value) = self.timers.get_mut(&table_oid) {
+            value
+        } else {
+            warning!(
+                "quartz-timer: failed to reschedule timer {}: table is not tracked",
+                TimerId::new(table_oid, row.id)
+            );
+
+            return;
+        };
+
+        // A timer that is not armed has already expired. If it is still pending
+        // (e.g. a recurring timer that was moved to its next expiration), it
+        // has to be armed again.
+        let entry = if let Some(value) = scoped_timers.get_mut(&row.id) {
+            value
+        } else {
+            self.create_timer(table_oid, row.into(), false);
 
-        let row: TimerRow = row.into();
-        let row_id = row.id;
-        let table_oid = table_oid;
+            return;
+        };
 
-        let handle = tokio::spawn(async move {
-            let now = Local::now();
+        entry.row.expires_at = row.expires_at;
+        entry.row.recurrence = row.recurrence;
+        entry.misfired = false;
 
-            if now <= row.expires_at {
-                let duration = row.expires_at - now;
+        self.arm_timer(table_oid, row.id, row.expires_at, false);
 
-                log!(
-                    "quartz-timer: timer {} ({}) is due in {}",
-                    row_id,
-                    table_oid,
-                    duration,
-                );
+        config::log_activity!(
+            "quartz-timer: timer {} rescheduled to {}",
+            TimerId::new(table_oid, row.id),
+            row.expires_at
+        );
+    }
 
-                time::sleep(duration.to_std().unwrap()).await;
-            } else {
-                log!(
-                    "quartz-timer: timer {} ({}) is already expired",
-                    row_id,
-                    table_oid,
-                );
+    fn cancel_timer(&mut self, table_oid: Oid, id: i64) {
+        let scoped_timers = if let Some(value) = self.timers.get_mut(&table_oid) {
+            value
+        } else {
+            warning!(
+                "quartz-timer: failed to cancel timer {}: table is not tracked",
+                TimerId::new(table_oid, id)
+            );
+
+            return;
+        };
+
+        if scoped_timers.remove(&id).is_some() {
+            self.deadlines.remove(&(table_oid.as_u32(), id));
+
+            if let Some(table) = stats::database(self.slot).table(table_oid) {
+                table.cancelled.incr();
             }
 
-            timer_handle.enqueue_event(TimerSubsystemEvent::ExpireTimer {
-                table_oid,
-                timer_id: row.id,
-            });
-        })
-        .abort_handle();
+            config::log_activity!("quartz-timer: timer {} cancelled", TimerId::new(table_oid, id));
+        } else {
+            warning!(
+                "quartz-timer: failed to cancel timer {}: timer is not armed",
+                TimerId::new(table_oid, id)
+            );
+        }
+    }
 
-        scoped_timers.insert(
-            row.id,
-            TimerEntry {
-                oid: table_oid,
-                row,
-                handle,
+    /// Set the deadline of a timer, replacing its previous one.
+    ///
+    /// A timer whose expiration has long passed has misfired, and is handled
+    /// according to the misfire policy of its table. Unless it is caught up
+    /// at a limited rate, it is due right away, and the workers decide whether
+    /// to fire it. Redeliveries of timers that have fired already never
+    /// misfire.
+    fn arm_timer(&mut self, table_oid: Oid, row_id: i64, expires_at: DateTime<Local>, redelivery: bool) {
+        let now = Local::now();
+
+        let misfires = self.misfires.get_mut(&table_oid);
+        let misfired = !redelivery
+            && misfires
+                .as_ref()
+                .is_some_and(|misfires| misfires.policy.is_misfired(expires_at, now));
+
+        let deadline = match misfires {
+            Some(misfires) if misfired => match misfires.policy.action {
+                MisfireAction::CatchUp(rate) => {
+                    let deadline = misfires.next_catch_up.max(now);
+                    let interval = chrono::Duration::microseconds((1e6 / rate) as i64);
+
+                    misfires.next_catch_up = deadline + interval;
+
+                    deadline
+                }
+                _ => expires_at,
             },
-        );
+            _ => expires_at,
+        };
+
+        if let Some(entry) = self
+            .timers
+            .get_mut(&table_oid)
+            .and_then(|timers| timers.get_mut(&row_id))
+        {
+            entry.misfired = misfired;
+        }
+
+        if misfired {
+            config::log_activity!(
+                "quartz-timer: timer {} misfired, it expired {} ago",
+                TimerId::new(table_oid, row_id),
+                now - expires_at,
+            );
+        } else if now <= expires_at {
+            config::log_activity!(
+                "quartz-timer: timer {} is due in {}",
+                TimerId::new(table_oid, row_id),
+                expires_at - now,
+            );
+        } else {
+            config::log_activity!(
+                "quartz-timer: timer {} is already expired",
+                TimerId::new(table_oid, row_id),
+            );
+        }
+
+        self.deadlines.insert((table_oid.as_u32(), row_id), deadline);
     }
 
-    fn expire_timer(&mut self, oid: Oid, id: i64) {
-        let scoped_timers = self.timers.entry(oid).or_insert_with(Default::default);
+    /// Hand a due timer over to the workers.
+    ///
+    /// Returns false if the workers queue is full, in which case the timer
+    /// stays armed.
+    fn expire_timer(&mut self, (oid, id): TimerKey) -> bool {
+        // The deadlines only hold keys of tracked timers.
+        let oid = unsafe { Oid::from_u32_unchecked(oid) };
 
-        if let Some(entry) = scoped_timers.remove(&id) {
-            let event = TimerFiredEvent {
-                table_oid: entry.oid,
-                row: entry.row,
-            };
+        let scoped_timers = if let Some(value) = self.timers.get_mut(&oid) {
+            value
+        } else {
+            warning!("quartz-timer: timer {} does not exist", TimerId::new(oid, id));
+
+            return true;
+        };
 
-            self.workers_handle
-                .enqueue_event(WorkerSubsystemEvent::TimerFired(event));
+        let entry = if let Some(value) = scoped_timers.get(&id) {
+            value
         } else {
-            error!("timer {} does not exist", id)
+            warning!("quartz-timer: timer {} does not exist", TimerId::new(oid, id));
+
+            return true;
+        };
+
+        let event = TimerFiredEvent {
+            table_oid: entry.oid,
+            row: entry.row.clone(),
+            misfired: entry.misfired,
+        };
+
+        if !self
+            .workers_handle
+            .enqueue_event(WorkerSubsystemEvent::TimerFired(event))
+        {
+            return false;
         }
+
+        scoped_timers.remove(&id);
+
+        true
     }
 
-    fn track_timers_table(&mut self, oid: Oid) {
+    fn track_timers_table(&mut self, oid: Oid, misfire_policy: MisfirePolicy) {
         if self.timers.contains_key(&oid) {
             warning!("table {} is already tracked", oid);
 
@@ -403,10 +1373,26 @@ impl Timer {
         }
 
         self.timers.insert(oid, Default::default());
+        self.misfires.insert(
+            oid,
+            Misfires {
+                policy: misfire_policy,
+                next_catch_up: Local::now(),
+            },
+        );
 
         info!("table {} is now tracked", oid)
     }
 
+    /// Change the misfire policy of a table. Armed timers keep their
+    /// deadlines.
+    fn update_misfire_policy(&mut self, oid: Oid, misfire_policy: MisfirePolicy) {
+        match self.misfires.get_mut(&oid) {
+            Some(misfires) => misfires.policy = misfire_policy,
+            None => warning!("table {} is not tracked", oid),
+        }
+    }
+
     fn untrack_timers_table(&mut self, oid: Oid) {
         let mut scoped_timers = if let Some(value) = self.timers.remove(&oid) {
             value
@@ -416,8 +1402,11 @@ impl Timer {
             return;
         };
 
-        for (_, entry) in scoped_timers.drain() {
-            entry.handle.abort();
+        self.misfires.remove(&oid);
+        stats::database(self.slot).release_table(oid);
+
+        for (id, _) in scoped_timers.drain() {
+            self.deadlines.remove(&(oid.as_u32(), id));
         }
     }
 }
@@ -1,6 +1,9 @@
 // src/time.rs
 
 use chrono::prelude::*;
+use chrono::LocalResult;
+use chrono::Months;
+use pgrx::Interval;
 use pgrx::TimestampWithTimeZone;
 
 // (January 1, 2000, UTC) - (January 1, 1970, UTC) as seconds;
@@ -20,8 +23,204 @@ pub fn pg_to_chrono(ts: TimestampWithTimeZone) -> DateTime<Local> {
 
 // chrono_to_pg converts a chrono::DateTime<Local> to a pgrx::Timestamp.
 pub fn chrono_to_pg(ts: DateTime<Local>) -> TimestampWithTimeZone {
-    let naive_ts = ts.naive_local(); // fixme: convert back to UTC
+    let naive_ts = ts.naive_utc();
     let ts_i64 = naive_ts.timestamp_micros() - PG_EPOCH_MICROS;
     TimestampWithTimeZone::try_from(ts_i64)
         .expect("chrono_to_pg_timestamp: timestamp out of range")
 }
+
+// CalendarInterval is an interval that is added to timestamps the way Postgres
+// adds an interval to a timestamp with time zone: months and days move the
+// local date, while the rest is elapsed time.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CalendarInterval {
+    pub months: i32,
+    pub days: i32,
+    pub micros: i64,
+}
+
+impl CalendarInterval {
+    // add_to adds the interval `n` times to a timestamp, like `ts + n * interval`
+    // in Postgres.
+    //
+    // Days past the end of a shorter month are clamped to its last day. A local
+    // time that is skipped by a daylight saving transition is taken with the
+    // offset from before the transition, and a local time that is repeated by
+    // one at its later instance.
+    pub fn add_to<Tz: TimeZone>(&self, ts: &DateTime<Tz>, n: i32) -> Option<DateTime<Tz>> {
+        let months = self.months.checked_mul(n)?;
+        let days = (self.days as i64).checked_mul(n as i64)?;
+        let micros = self.micros.checked_mul(n as i64)?;
+
+        let ts = if months == 0 && days == 0 {
+            ts.clone()
+        } else {
+            let local = ts.naive_local();
+            let local = if months >= 0 {
+                local.checked_add_months(Months::new(months as u32))?
+            } else {
+                local.checked_sub_months(Months::new(months.unsigned_abs()))?
+            };
+            let local = local.checked_add_signed(chrono::Duration::days(days))?;
+
+            match ts.timezone().from_local_datetime(&local) {
+                LocalResult::Single(value) => value,
+                LocalResult::Ambiguous(_, latest) => latest,
+                LocalResult::None => {
+                    // Transitions are more than a day apart.
+                    let before = ts
+                        .timezone()
+                        .offset_from_utc_datetime(&(local - chrono::Duration::days(1)))
+                        .fix();
+                    let utc = local - chrono::Duration::seconds(before.local_minus_utc() as i64);
+
+                    ts.timezone().from_utc_datetime(&utc)
+                }
+            }
+        };
+
+        ts.checked_add_signed(chrono::Duration::microseconds(micros))
+    }
+}
+
+impl From<Interval> for CalendarInterval {
+    fn from(interval: Interval) -> Self {
+        Self {
+            months: interval.months(),
+            days: interval.days(),
+            micros: interval.micros(),
+        }
+    }
+}
+
+impl From<CalendarInterval> for Interval {
+    fn from(interval: CalendarInterval) -> Self {
+        Interval::try_from_months_days_micros(interval.months, interval.days, interval.micros)
+            .expect("CalendarInterval: interval out of range")
+    }
+}
+
+// chrono_to_pg_interval converts a chrono::Duration to a pgrx::Interval.
+pub fn chrono_to_pg_interval(duration: chrono::Duration) -> Interval {
+    let micros = duration
+        .num_microseconds()
+        .expect("chrono_to_pg_interval: duration out of range");
+    Interval::try_from_months_days_micros(0, 0, micros)
+        .expect("chrono_to_pg_interval: duration out of range")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Central European Time in 2023, which switches to summer time from
+    // 2023-03-26 01:00 UTC until 2023-10-29 01:00 UTC.
+    #[derive(Copy, Clone, Debug)]
+    pub(crate) struct Cet2023;
+
+    impl Cet2023 {
+        fn offset_at(utc: &NaiveDateTime) -> FixedOffset {
+            let summer_start = NaiveDate::from_ymd_opt(2023, 3, 26).unwrap().and_hms_opt(1, 0, 0).unwrap();
+            let summer_end = NaiveDate::from_ymd_opt(2023, 10, 29).unwrap().and_hms_opt(1, 0, 0).unwrap();
+            let hours = if (summer_start..summer_end).contains(utc) { 2 } else { 1 };
+
+            FixedOffset::east_opt(hours * 3600).unwrap()
+        }
+    }
+
+    impl TimeZone for Cet2023 {
+        type Offset = FixedOffset;
+
+        fn from_offset(_offset: &FixedOffset) -> Self {
+            Cet2023
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            // The summer time offset comes first, since it is the earlier
+            // instance of a repeated local time.
+            let offsets: Vec<FixedOffset> = [2, 1]
+                .iter()
+                .map(|hours| FixedOffset::east_opt(hours * 3600).unwrap())
+                .filter(|offset| {
+                    let utc = *local - chrono::Duration::seconds(offset.local_minus_utc() as i64);
+                    Self::offset_at(&utc) == *offset
+                })
+                .collect();
+
+            match offsets[..] {
+                [offset] => LocalResult::Single(offset),
+                [earliest, latest] => LocalResult::Ambiguous(earliest, latest),
+                _ => LocalResult::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            Self::offset_at(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            Self::offset_at(utc)
+        }
+    }
+
+    const HOUR: i64 = 3_600_000_000;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn cet(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Cet2023> {
+        Cet2023.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn interval(months: i32, days: i32, micros: i64) -> CalendarInterval {
+        CalendarInterval { months, days, micros }
+    }
+
+    #[test]
+    fn test_calendar_interval_months() {
+        // Days past the end of a shorter month are clamped to its last day.
+        let month = interval(1, 0, 0);
+        assert_eq!(month.add_to(&utc(2023, 1, 31, 9, 0), 1), Some(utc(2023, 2, 28, 9, 0)));
+        assert_eq!(month.add_to(&utc(2023, 1, 31, 9, 0), 2), Some(utc(2023, 3, 31, 9, 0)));
+        assert_eq!(month.add_to(&utc(2023, 1, 31, 9, 0), 13), Some(utc(2024, 2, 29, 9, 0)));
+        assert_eq!(month.add_to(&utc(2023, 3, 31, 9, 0), -1), Some(utc(2023, 2, 28, 9, 0)));
+
+        let year = interval(12, 0, 0);
+        assert_eq!(year.add_to(&utc(2024, 2, 29, 9, 0), 1), Some(utc(2025, 2, 28, 9, 0)));
+    }
+
+    #[test]
+    fn test_calendar_interval_days_across_transitions() {
+        // A day keeps the local time across a transition, 24 hours do not.
+        let day = interval(0, 1, 0);
+        let hours = interval(0, 0, 24 * HOUR);
+
+        assert_eq!(day.add_to(&cet(2023, 3, 25, 12, 0), 1), Some(cet(2023, 3, 26, 12, 0)));
+        assert_eq!(hours.add_to(&cet(2023, 3, 25, 12, 0), 1), Some(cet(2023, 3, 26, 13, 0)));
+        assert_eq!(day.add_to(&cet(2023, 10, 28, 12, 0), 1), Some(cet(2023, 10, 29, 12, 0)));
+        assert_eq!(hours.add_to(&cet(2023, 10, 28, 12, 0), 1), Some(cet(2023, 10, 29, 11, 0)));
+
+        // Days move the local time before the rest is added as elapsed time.
+        let mixed = interval(0, 1, 2 * HOUR);
+        assert_eq!(mixed.add_to(&cet(2023, 3, 25, 1, 0), 1), Some(cet(2023, 3, 26, 4, 0)));
+    }
+
+    #[test]
+    fn test_calendar_interval_skipped_and_repeated_local_times() {
+        let day = interval(0, 1, 0);
+
+        // 02:30 is skipped on 2023-03-26, and taken with the offset from
+        // before the transition, i.e. as 03:30 summer time.
+        let next = day.add_to(&cet(2023, 3, 25, 2, 30), 1).unwrap();
+        assert_eq!(next.with_timezone(&Utc), utc(2023, 3, 26, 1, 30));
+
+        // 02:30 is repeated on 2023-10-29, and taken at its later instance.
+        let next = day.add_to(&cet(2023, 10, 28, 2, 30), 1).unwrap();
+        assert_eq!(next.with_timezone(&Utc), utc(2023, 10, 29, 1, 30));
+    }
+}
@@ -3,9 +3,14 @@
 use chrono::prelude::*;
 use pgrx::prelude::*;
 
+use crate::cron::CronSchedule;
 use crate::timer::TimerHandle;
 use crate::timer::TimerSubsystemEvent;
+use crate::timestamp;
+use crate::types::ColumnMapping;
 use crate::types::CreateTimerFromRow;
+use crate::types::TimerRow;
+use crate::workers::WorkersHandle;
 
 use std::convert::TryFrom;
 
@@ -26,6 +31,31 @@ macro_rules! assert_row_trigger_event {
     };
 }
 
+/// The column mapping of the table that a trigger fired on, which is passed to
+/// the trigger as its arguments. Triggers without arguments use the default
+/// mapping.
+fn trigger_columns(trigger: &PgTrigger<'_>) -> ColumnMapping {
+    let args = match trigger.extra_args() {
+        Ok(value) => value,
+        Err(e) => error!("trigger arguments are unexpectedly unavailable: {}", e),
+    };
+
+    if args.is_empty() {
+        return ColumnMapping::default();
+    }
+
+    // Roles without a column are passed as empty strings.
+    let columns = args
+        .into_iter()
+        .map(|arg| Some(arg).filter(|arg| !arg.is_empty()))
+        .collect::<Vec<_>>();
+
+    match ColumnMapping::from_columns(columns) {
+        Ok(value) => value,
+        Err(e) => error!("invalid column mapping in trigger arguments: {}", e),
+    }
+}
+
 pub fn quartz_timers_before_insert<'a>(
     trigger: &'a PgTrigger<'a>,
 ) -> TriggerResult<'a, impl WhoAllocated> {
@@ -40,19 +70,45 @@ pub fn quartz_timers_before_insert<'a>(
         }
     );
 
-    let new_row = trigger
+    let columns = trigger_columns(trigger);
+
+    let mut new_row = trigger
         .new()
-        .expect("before insert trigger must have \"new\"");
+        .expect("before insert trigger must have \"new\"")
+        .into_owned();
+
+    // Scheduled timers may leave their first expiration to the schedule.
+    let schedule = columns
+        .schedule
+        .as_ref()
+        .map(|column| new_row.get_by_name::<String>(column.as_str()));
+
+    if let (Ok(None), Some(Ok(Some(schedule)))) = (
+        new_row.get_by_name::<TimestampWithTimeZone>(columns.expires_at.as_str()),
+        schedule,
+    ) {
+        let next = match CronSchedule::parse(&schedule) {
+            Ok(value) => value.next_after(&now),
+            Err(e) => error!("create new timer: {}", e),
+        };
+
+        let next = match next {
+            Some(value) => timestamp::chrono_to_pg(value),
+            None => error!("create new timer: schedule \"{}\" never occurs", schedule),
+        };
+
+        if let Err(e) = new_row.set_by_name(columns.expires_at.as_str(), next) {
+            error!("create new timer: {}", e);
+        }
+    }
 
-    let new_timer = match CreateTimerFromRow::try_from(&new_row) {
+    let new_timer = match CreateTimerFromRow::try_from((&new_row, &columns)) {
         Ok(value) => value,
         Err(e) => {
             error!("create new timer: {}", e);
         }
     };
 
-    // fixme: ensure that timers are unique across schemas and tables
-
     if now >= new_timer.expires_at {
         error!(
             "timer is in the past: now={}, new.ts={}",
@@ -75,11 +131,13 @@ pub fn quartz_timers_after_insert<'a>(
         }
     );
 
+    let columns = trigger_columns(trigger);
+
     let new_row = trigger
         .new()
         .expect("before insert trigger must have \"new\"");
 
-    let new_timer = match CreateTimerFromRow::try_from(&new_row) {
+    let new_timer = match CreateTimerFromRow::try_from((&new_row, &columns)) {
         Ok(value) => value,
         Err(e) => {
             error!("create new timer: {}", e);
@@ -96,7 +154,7 @@ pub fn quartz_timers_after_insert<'a>(
         table_row: new_timer,
     };
 
-    if !TimerHandle::get().enqueue_event(event) {
+    if !TimerHandle::get().enqueue_event_on_commit(event) {
         error!("failed to enqueue timer")
     }
 
@@ -106,6 +164,8 @@ pub fn quartz_timers_after_insert<'a>(
 pub fn quartz_timers_before_update<'a>(
     trigger: &'a PgTrigger<'a>,
 ) -> TriggerResult<'a, impl WhoAllocated> {
+    let now = Local::now();
+
     assert_row_trigger_event!(
         trigger.event(),
         quartz_timers_before_update => {
@@ -115,15 +175,70 @@ pub fn quartz_timers_before_update<'a>(
         }
     );
 
-    notice!("quartz.quartz_timers_before_update: updates are not yet supported");
+    let columns = trigger_columns(trigger);
+
+    let old_row = trigger
+        .old()
+        .expect("before update trigger must have \"old\"");
+    let new_row = trigger
+        .new()
+        .expect("before update trigger must have \"new\"");
+
+    let old_timer = match TimerRow::try_from((&old_row, &columns)) {
+        Ok(value) => value,
+        Err(e) => {
+            error!("update timer: old row: {}", e);
+        }
+    };
+    let new_timer = match TimerRow::try_from((&new_row, &columns)) {
+        Ok(value) => value,
+        Err(e) => {
+            error!("update timer: new row: {}", e);
+        }
+    };
+
+    if old_timer.id != new_timer.id {
+        error!(
+            "update timer: id must not be changed: old.id={}, new.id={}",
+            old_timer.id, new_timer.id
+        );
+    }
+
+    if old_timer.fired_at.is_some() && new_timer.fired_at.is_none() {
+        error!("update timer {}: fired_at must not be cleared", new_timer.id);
+    }
+
+    if old_timer.completed_at.is_some() && new_timer.completed_at.is_none() {
+        error!("update timer {}: completed_at must not be cleared", new_timer.id);
+    }
+
+    if new_timer.completed_at.is_some() && new_timer.fired_at.is_none() {
+        error!("update timer {}: completed_at requires fired_at", new_timer.id);
+    }
+
+    if old_timer.missed_at.is_some() && new_timer.missed_at.is_none() {
+        error!("update timer {}: missed_at must not be cleared", new_timer.id);
+    }
+
+    if old_timer.expires_at != new_timer.expires_at {
+        if old_timer.fired_at.is_some() || new_timer.fired_at.is_some() {
+            error!("update timer {}: cannot reschedule a fired timer", new_timer.id);
+        }
+
+        if old_timer.completed_at.is_some() || new_timer.completed_at.is_some() {
+            error!("update timer {}: cannot reschedule a completed timer", new_timer.id);
+        }
+
+        if old_timer.missed_at.is_some() || new_timer.missed_at.is_some() {
+            error!("update timer {}: cannot reschedule a missed timer", new_timer.id);
+        }
 
-    // todo: validate
-    // - parse Timer out of new
-    // - ensure ID is not changed
-    // - ensure one of EXP_AT, FIRED_AT or ACK_AT are updated
-    // - ensure EXP_AT is not updated if FIRED_AT or ACK_AT are set
+        if now >= new_timer.expires_at {
+            error!("update timer {}: expires_at is in the past", new_timer.id);
+        }
+    }
 
-    Ok(trigger.new())
+    Ok(Some(new_row))
 }
 
 pub fn quartz_timers_after_update<'a>(
@@ -131,24 +246,56 @@ pub fn quartz_timers_after_update<'a>(
 ) -> TriggerResult<'a, impl WhoAllocated> {
     assert_row_trigger_event!(
         trigger.event(),
-        quartz_timers_before_update => {
+        quartz_timers_after_update => {
             fired_by_update,
             fired_after,
             fired_for_row
         }
     );
 
-    notice!("quartz.quartz_timers_after_update: updates are not yet supported");
+    let columns = trigger_columns(trigger);
+
+    let old_row = trigger
+        .old()
+        .expect("after update trigger must have \"old\"");
+    let new_row = trigger
+        .new()
+        .expect("after update trigger must have \"new\"");
+
+    let old_timer = match TimerRow::try_from((&old_row, &columns)) {
+        Ok(value) => value,
+        Err(e) => {
+            error!("update timer: old row: {}", e);
+        }
+    };
+    let new_timer = match TimerRow::try_from((&new_row, &columns)) {
+        Ok(value) => value,
+        Err(e) => {
+            error!("update timer: new row: {}", e);
+        }
+    };
+
+    // Only changes of expires_at are relevant to the timer subsystem, the
+    // other columns are managed by quartz itself.
+    if old_timer.expires_at == new_timer.expires_at {
+        return Ok(Some(new_row));
+    }
+
+    let relation_oid = match trigger.relation().map(|rel| rel.oid()) {
+        Ok(value) => value,
+        Err(e) => error!("quartz_timers_after_update: relation ID is unexpectedly unavailable: {}", e),
+    };
+
+    let event = TimerSubsystemEvent::RescheduleTimer {
+        table_oid: relation_oid,
+        table_row: new_timer.into(),
+    };
 
-    // todo: queue update
-    // - parse Timer out of new
-    // - ensure ID is not changed
-    // - ensure one of EXP_AT, FIRED_AT or ACK_AT are updated
-    // - ensure EXP_AT is not updated if FIRED_AT or ACK_AT are set
-    // - ignore FIRED_AT or ACK_AT
-    // - queue update to be applied
+    if !TimerHandle::get().enqueue_event_on_commit(event) {
+        error!("failed to enqueue timer")
+    }
 
-    Ok(trigger.new())
+    Ok(Some(new_row))
 }
 
 pub fn quartz_timers_before_delete<'a>(
@@ -156,21 +303,36 @@ pub fn quartz_timers_before_delete<'a>(
 ) -> TriggerResult<'a, impl WhoAllocated> {
     assert_row_trigger_event!(
         trigger.event(),
-        quartz_timers_before_update => {
+        quartz_timers_before_delete => {
             fired_by_delete,
             fired_before,
             fired_for_row
         }
     );
 
-    notice!("quartz.quartz_timers_before_delete: deletes are not yet supported");
+    let columns = trigger_columns(trigger);
 
-    // todo: validate
-    // - parse Timer out of old
-    // - check that ID exists
-    // - revoke delete if timer is currently firing
+    let old_row = trigger
+        .old()
+        .expect("before delete trigger must have \"old\"");
 
-    Ok(trigger.old())
+    let old_timer = match TimerRow::try_from((&old_row, &columns)) {
+        Ok(value) => value,
+        Err(e) => {
+            error!("delete timer: {}", e);
+        }
+    };
+
+    let relation_oid = match trigger.relation().map(|rel| rel.oid()) {
+        Ok(value) => value,
+        Err(e) => error!("quartz_timers_before_delete: relation ID is unexpectedly unavailable: {}", e),
+    };
+
+    if WorkersHandle::get().is_timer_firing(relation_oid, old_timer.id) {
+        error!("delete timer {}: timer is currently firing", old_timer.id);
+    }
+
+    Ok(Some(old_row))
 }
 
 pub fn quartz_timers_after_delete<'a>(
@@ -178,21 +340,221 @@ pub fn quartz_timers_after_delete<'a>(
 ) -> TriggerResult<'a, impl WhoAllocated> {
     assert_row_trigger_event!(
         trigger.event(),
-        quartz_timers_before_update => {
+        quartz_timers_after_delete => {
             fired_by_delete,
             fired_after,
             fired_for_row
         }
     );
 
-    notice!("quartz.quartz_timers_after_delete: deletes are not yet supported");
+    let columns = trigger_columns(trigger);
+
+    let old_row = trigger
+        .old()
+        .expect("after delete trigger must have \"old\"");
+
+    let old_timer = match TimerRow::try_from((&old_row, &columns)) {
+        Ok(value) => value,
+        Err(e) => {
+            error!("delete timer: {}", e);
+        }
+    };
+
+    // Fired and missed timers are no longer armed, so there is nothing to
+    // cancel.
+    if old_timer.fired_at.is_some() || old_timer.missed_at.is_some() {
+        return Ok(Some(old_row));
+    }
+
+    let relation_oid = match trigger.relation().map(|rel| rel.oid()) {
+        Ok(value) => value,
+        Err(e) => error!("quartz_timers_after_delete: relation ID is unexpectedly unavailable: {}", e),
+    };
+
+    let event = TimerSubsystemEvent::CancelTimer {
+        table_oid: relation_oid,
+        timer_id: old_timer.id,
+    };
+
+    if !TimerHandle::get().enqueue_event_on_commit(event) {
+        error!("failed to enqueue timer")
+    }
+
+    Ok(Some(old_row))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    /// Create a timers table with a single pending timer with ID 1.
+    fn create_pending_timer() {
+        Spi::run("select quartz.create_timers_table('timers')").unwrap();
+        Spi::run("insert into timers (expires_at) values (now() + interval '1 hour')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_reschedule_pending_timer() {
+        create_pending_timer();
 
-    // todo: queue delete
-    // - parse Timer out of old
-    // - check that ID exists
-    // - revoke delete if timer is currently firing
-    // - if fired, ignore
-    // - queue delete to be applied
+        Spi::run("update timers set expires_at = now() + interval '2 hours'").unwrap();
 
-    Ok(trigger.old())
+        let expires_at = Spi::get_one::<bool>("select expires_at = now() + interval '2 hours' from timers");
+        assert_eq!(expires_at, Ok(Some(true)));
+    }
+
+    // The timer subsystem only learns of committed changes, while `#[pg_test]`
+    // rolls back every test, so this test commits through its own connection.
+    #[cfg(test)]
+    #[test]
+    fn test_reschedule_pending_timer_in_subsystem() {
+        // This also starts the test server, unless another test already has.
+        pgrx_tests::run_test(
+            "test_reschedule_pending_timer",
+            None,
+            crate::pg_test::postgresql_conf_options(),
+        )
+        .unwrap();
+
+        let (mut client, _) = pgrx_tests::client().unwrap();
+        let armed = "select exists (
+            select from quartz.scheduled_timers() s
+            join rescheduled_timers t on s.id = t.id and s.expires_at = t.expires_at
+            where s.relid = 'rescheduled_timers'::regclass
+        )";
+
+        client
+            .batch_execute(
+                "select quartz.create_timers_table('rescheduled_timers');
+                 insert into rescheduled_timers (expires_at) values (now() + interval '1 hour');",
+            )
+            .unwrap();
+        wait_until("the timer is armed", || client.query_one(armed, &[]).unwrap().get(0));
+
+        client
+            .batch_execute("update rescheduled_timers set expires_at = expires_at + interval '1 hour'")
+            .unwrap();
+        wait_until("the timer is rearmed", || client.query_one(armed, &[]).unwrap().get(0));
+
+        client.batch_execute("drop table rescheduled_timers").unwrap();
+    }
+
+    #[cfg(test)]
+    fn wait_until(what: &str, mut condition: impl FnMut() -> bool) {
+        for _ in 0..100 {
+            if condition() {
+                return;
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        panic!("timed out waiting until {}", what);
+    }
+
+    #[pg_test(error = "update timer 1: expires_at is in the past")]
+    fn test_reschedule_into_the_past() {
+        create_pending_timer();
+
+        Spi::run("update timers set expires_at = now() - interval '1 hour'").unwrap();
+    }
+
+    #[pg_test(error = "update timer 1: fired_at must not be cleared")]
+    fn test_clear_fired_at() {
+        create_pending_timer();
+
+        Spi::run("update timers set fired_at = now()").unwrap();
+        Spi::run("update timers set fired_at = null").unwrap();
+    }
+
+    #[pg_test(error = "update timer 1: completed_at must not be cleared")]
+    fn test_clear_completed_at() {
+        create_pending_timer();
+
+        Spi::run("update timers set fired_at = now(), completed_at = now()").unwrap();
+        Spi::run("update timers set completed_at = null").unwrap();
+    }
+
+    #[pg_test(error = "update timer 1: completed_at requires fired_at")]
+    fn test_complete_unfired_timer() {
+        create_pending_timer();
+
+        Spi::run("update timers set completed_at = now()").unwrap();
+    }
+
+    #[pg_test(error = "update timer 1: cannot reschedule a fired timer")]
+    fn test_reschedule_fired_timer() {
+        create_pending_timer();
+
+        Spi::run("update timers set fired_at = now()").unwrap();
+        Spi::run("update timers set expires_at = expires_at + interval '1 hour'").unwrap();
+    }
+
+    #[pg_test(error = "update timer 1: missed_at must not be cleared")]
+    fn test_clear_missed_at() {
+        create_pending_timer();
+
+        Spi::run("update timers set missed_at = now()").unwrap();
+        Spi::run("update timers set missed_at = null").unwrap();
+    }
+
+    #[pg_test(error = "update timer 1: cannot reschedule a missed timer")]
+    fn test_reschedule_missed_timer() {
+        create_pending_timer();
+
+        Spi::run("update timers set missed_at = now()").unwrap();
+        Spi::run("update timers set expires_at = expires_at + interval '1 hour'").unwrap();
+    }
+
+    #[pg_test]
+    fn test_insert_scheduled_timer_without_expiration() {
+        Spi::run("select quartz.create_timers_table('timers')").unwrap();
+        Spi::run("insert into timers (schedule) values ('@hourly')").unwrap();
+
+        // The first expiration is the next full hour.
+        let expires_at = Spi::get_one::<bool>(
+            "select expires_at > now() and expires_at = date_trunc('hour', expires_at) from timers",
+        );
+        assert_eq!(expires_at, Ok(Some(true)));
+    }
+
+    #[pg_test(error = "create new timer: cron value 25 is out of range 0-23")]
+    fn test_insert_invalid_schedule() {
+        Spi::run("select quartz.create_timers_table('timers')").unwrap();
+        Spi::run("insert into timers (schedule) values ('0 25 * * *')").unwrap();
+    }
+
+    #[pg_test(error = "create new timer: schedule \"0 0 30 2 *\" never occurs")]
+    fn test_insert_schedule_that_never_occurs() {
+        Spi::run("select quartz.create_timers_table('timers')").unwrap();
+        Spi::run("insert into timers (schedule) values ('0 0 30 2 *')").unwrap();
+    }
+
+    #[pg_test(error = "create new timer: repeat_every must not have negative parts")]
+    fn test_insert_negative_repeat_every() {
+        Spi::run("select quartz.create_timers_table('timers')").unwrap();
+        Spi::run(
+            "insert into timers (expires_at, repeat_every) values (now() + interval '1 hour', '1 month -1 day')",
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "create new timer: repeat_every and schedule are mutually exclusive")]
+    fn test_insert_repeat_every_and_schedule() {
+        Spi::run("select quartz.create_timers_table('timers')").unwrap();
+        Spi::run(
+            "insert into timers (expires_at, repeat_every, schedule) values (now() + interval '1 hour', '1 day', '@daily')",
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "create new timer: repeat_until requires repeat_every or schedule")]
+    fn test_insert_repeat_until_without_recurrence() {
+        Spi::run("select quartz.create_timers_table('timers')").unwrap();
+        Spi::run(
+            "insert into timers (expires_at, repeat_until) values (now() + interval '1 hour', now() + interval '1 day')",
+        )
+        .unwrap();
+    }
 }
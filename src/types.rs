@@ -2,13 +2,25 @@
 
 use chrono::prelude::*;
 use pgrx::TryFromDatumError;
+use pgrx::pg_sys::Oid;
 use pgrx::prelude::*;
 
+use crate::cron::CronSchedule;
+use crate::timestamp::CalendarInterval;
+
+use std::collections::hash_map::RandomState;
 use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
 
 /// A row in a timer table.
 ///
 /// This is agnostic towards the actual table that the timer is associated with.
+/// Optional columns such as `payload` are left out, since rows are passed
+/// through shared memory. Tables that do not map the other optional roles
+/// read as having no attempts, no recurrence, no occurrences and no misses.
+#[derive(Clone)]
 pub struct TimerRow {
     // The ID of the timer.
     pub id: i64,
@@ -22,64 +34,313 @@ pub struct TimerRow {
     // The timestamp at which the timer firing was acknowledged, if it has been
     // fired and the action has been successfully completed.
     pub completed_at: Option<chrono::DateTime<Local>>,
+
+    // The number of failed attempts at firing the timer.
+    pub attempts: i32,
+
+    // The recurrence of the timer, if it is re-scheduled after firing.
+    pub recurrence: Option<Recurrence>,
+
+    // The number of times the timer has fired.
+    pub occurrences: i32,
+
+    // The timestamp at which the timer was skipped, if it misfired and its
+    // table's misfire policy did not fire it.
+    pub missed_at: Option<chrono::DateTime<Local>>,
 }
 
-impl<'a> TryFrom<&'a PgHeapTuple<'a, AllocatedByPostgres>> for TimerRow {
+impl<'a, W: WhoAllocated> TryFrom<(&'a PgHeapTuple<'a, W>, &'a ColumnMapping)> for TimerRow {
     type Error = Box<dyn Error>;
 
-    fn try_from(tuple: &'a PgHeapTuple<'a, AllocatedByPostgres>) -> Result<Self, Self::Error> {
-        let id = match tuple.get_by_name("id") {
+    fn try_from(
+        (tuple, columns): (&'a PgHeapTuple<'a, W>, &'a ColumnMapping),
+    ) -> Result<Self, Self::Error> {
+        let id = match tuple.get_by_name(columns.id.as_str()) {
             Ok(Some(value)) => value,
-            Ok(None) => return Err("id must not be null".into()),
-            Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err("missing id column".into()),
-            Err(TryFromDatumError::IncompatibleTypes{..}) => return Err("id must be a bigint".into()),
+            Ok(None) => return Err(format!("{} must not be null", columns.id).into()),
+            Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err(format!("missing {} column", columns.id).into()),
+            Err(TryFromDatumError::IncompatibleTypes{..}) => return Err(format!("{} must be a bigint", columns.id).into()),
             Err(e) => return Err(format!("unexpected error: {}", e).into()),
         };
 
-        let expires_at = match tuple.get_by_name::<TimestampWithTimeZone>("expires_at") {
+        let expires_at = match tuple.get_by_name::<TimestampWithTimeZone>(columns.expires_at.as_str()) {
             Ok(Some(value)) => crate::timestamp::pg_to_chrono(value),
-            Ok(None) => return Err("expires_at must not be null".into()),
-            Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err("missing expires_at column".into()),
-            Err(TryFromDatumError::IncompatibleTypes{..}) => return Err("expires_at must be a timestamp".into()),
+            Ok(None) => return Err(format!("{} must not be null", columns.expires_at).into()),
+            Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err(format!("missing {} column", columns.expires_at).into()),
+            Err(TryFromDatumError::IncompatibleTypes{..}) => return Err(format!("{} must be a timestamp", columns.expires_at).into()),
             Err(e) => return Err(format!("unexpected error: {}", e).into()),
         };
 
-        let fired_at = match tuple.get_by_name::<TimestampWithTimeZone>("fired_at") {
+        let fired_at = match tuple.get_by_name::<TimestampWithTimeZone>(columns.fired_at.as_str()) {
             Ok(Some(value)) => Some(crate::timestamp::pg_to_chrono(value)),
             Ok(None) => None,
-            Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err("missing fired_at column".into()),
-            Err(TryFromDatumError::IncompatibleTypes{..}) => return Err("fired_at must be a timestamp".into()),
+            Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err(format!("missing {} column", columns.fired_at).into()),
+            Err(TryFromDatumError::IncompatibleTypes{..}) => return Err(format!("{} must be a timestamp", columns.fired_at).into()),
             Err(e) => return Err(format!("unexpected error: {}", e).into()),
         };
 
-        let completed_at = match tuple.get_by_name::<TimestampWithTimeZone>("completed_at") {
+        let completed_at = match tuple.get_by_name::<TimestampWithTimeZone>(columns.completed_at.as_str()) {
             Ok(Some(value)) => Some(crate::timestamp::pg_to_chrono(value)),
             Ok(None) => None,
-            Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err("missing completed_at column".into()),
-            Err(TryFromDatumError::IncompatibleTypes{..}) => return Err("completed_at must be a timestamp".into()),
+            Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err(format!("missing {} column", columns.completed_at).into()),
+            Err(TryFromDatumError::IncompatibleTypes{..}) => return Err(format!("{} must be a timestamp", columns.completed_at).into()),
             Err(e) => return Err(format!("unexpected error: {}", e).into()),
         };
 
+        // Columns of optional roles are read if the table maps them.
+
+        let attempts = match &columns.attempts {
+            Some(column) => match tuple.get_by_name(column.as_str()) {
+                Ok(Some(value)) => value,
+                Ok(None) => return Err(format!("{} must not be null", column).into()),
+                Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err(format!("missing {} column", column).into()),
+                Err(TryFromDatumError::IncompatibleTypes{..}) => return Err(format!("{} must be an integer", column).into()),
+                Err(e) => return Err(format!("unexpected error: {}", e).into()),
+            },
+            None => 0,
+        };
+
+        let repeat_every = match &columns.repeat_every {
+            Some(column) => match tuple.get_by_name::<Interval>(column.as_str()) {
+                Ok(Some(value)) => Some(CalendarInterval::from(value)),
+                Ok(None) => None,
+                Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err(format!("missing {} column", column).into()),
+                Err(TryFromDatumError::IncompatibleTypes{..}) => return Err(format!("{} must be an interval", column).into()),
+                Err(e) => return Err(format!("unexpected error: {}", e).into()),
+            },
+            None => None,
+        };
+
+        let schedule = match &columns.schedule {
+            Some(column) => match tuple.get_by_name::<String>(column.as_str()) {
+                Ok(Some(value)) => Some(CronSchedule::parse(&value)?),
+                Ok(None) => None,
+                Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err(format!("missing {} column", column).into()),
+                Err(TryFromDatumError::IncompatibleTypes{..}) => return Err(format!("{} must be a text", column).into()),
+                Err(e) => return Err(format!("unexpected error: {}", e).into()),
+            },
+            None => None,
+        };
+
+        let repeat_until = match &columns.repeat_until {
+            Some(column) => match tuple.get_by_name::<TimestampWithTimeZone>(column.as_str()) {
+                Ok(Some(value)) => Some(crate::timestamp::pg_to_chrono(value)),
+                Ok(None) => None,
+                Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err(format!("missing {} column", column).into()),
+                Err(TryFromDatumError::IncompatibleTypes{..}) => return Err(format!("{} must be a timestamp", column).into()),
+                Err(e) => return Err(format!("unexpected error: {}", e).into()),
+            },
+            None => None,
+        };
+
+        let max_occurrences = match &columns.max_occurrences {
+            Some(column) => match tuple.get_by_name::<i32>(column.as_str()) {
+                Ok(value) => value,
+                Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err(format!("missing {} column", column).into()),
+                Err(TryFromDatumError::IncompatibleTypes{..}) => return Err(format!("{} must be an integer", column).into()),
+                Err(e) => return Err(format!("unexpected error: {}", e).into()),
+            },
+            None => None,
+        };
+
+        let occurrences = match &columns.occurrences {
+            Some(column) => match tuple.get_by_name(column.as_str()) {
+                Ok(Some(value)) => value,
+                Ok(None) => return Err(format!("{} must not be null", column).into()),
+                Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err(format!("missing {} column", column).into()),
+                Err(TryFromDatumError::IncompatibleTypes{..}) => return Err(format!("{} must be an integer", column).into()),
+                Err(e) => return Err(format!("unexpected error: {}", e).into()),
+            },
+            None => 0,
+        };
+
+        let missed_at = match &columns.missed_at {
+            Some(column) => match tuple.get_by_name::<TimestampWithTimeZone>(column.as_str()) {
+                Ok(Some(value)) => Some(crate::timestamp::pg_to_chrono(value)),
+                Ok(None) => None,
+                Err(TryFromDatumError::NoSuchAttributeName(_)) => return Err(format!("missing {} column", column).into()),
+                Err(TryFromDatumError::IncompatibleTypes{..}) => return Err(format!("{} must be a timestamp", column).into()),
+                Err(e) => return Err(format!("unexpected error: {}", e).into()),
+            },
+            None => None,
+        };
+
+        let recurrence = Recurrence::try_new(repeat_every, schedule, repeat_until, max_occurrences)?;
+
         Ok(Self {
             id,
             expires_at,
             fired_at,
             completed_at,
+            attempts,
+            recurrence,
+            occurrences,
+            missed_at,
         })
     }
 }
 
+/// The identity of a timer across all timers tables.
+///
+/// Timer IDs are only unique within their table, so a timer is identified by
+/// its table together with its ID, like the `quartz.timer_id` type. It is
+/// formatted with the OID of the table, e.g. `(16384,42)`, which does not
+/// depend on the search path. `quartz.timer_id` prints the table by name
+/// instead, e.g. `(orders,42)`, but accepts either form, so casting
+/// `'(16384,42)'` to `quartz.timer_id` refers to the same timer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TimerId {
+    // The OID of the timers table.
+    pub table_oid: Oid,
+
+    // The ID of the timer in the table.
+    pub id: i64,
+}
+
+impl TimerId {
+    pub fn new(table_oid: Oid, id: i64) -> Self {
+        Self { table_oid, id }
+    }
+}
+
+impl fmt::Display for TimerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({},{})", self.table_oid.as_u32(), self.id)
+    }
+}
+
+/// The columns of a timers table, by the role they play for its timers.
+///
+/// Tables created by `create_timers_table` name their columns after their
+/// roles, while tables registered by `register_timers_table` may map the roles
+/// onto columns of their own. Only the roles in `REQUIRED_ROLES` must be
+/// mapped, tables without the columns of the other roles go without retries,
+/// recurrence, missed timers or a payload.
+#[derive(Clone)]
+pub struct ColumnMapping {
+    pub id: String,
+    pub expires_at: String,
+    pub fired_at: String,
+    pub completed_at: String,
+    pub attempts: Option<String>,
+    pub last_error: Option<String>,
+    pub repeat_every: Option<String>,
+    pub schedule: Option<String>,
+    pub repeat_until: Option<String>,
+    pub max_occurrences: Option<String>,
+    pub occurrences: Option<String>,
+    pub missed_at: Option<String>,
+    pub payload: Option<String>,
+}
+
+impl ColumnMapping {
+    /// The roles of the columns, in the order of `from_columns` and `columns`.
+    pub const ROLES: [&'static str; 13] = [
+        "id",
+        "expires_at",
+        "fired_at",
+        "completed_at",
+        "attempts",
+        "last_error",
+        "repeat_every",
+        "schedule",
+        "repeat_until",
+        "max_occurrences",
+        "occurrences",
+        "missed_at",
+        "payload",
+    ];
+
+    /// The roles that every timers table must map onto a column.
+    pub const REQUIRED_ROLES: [&'static str; 4] = ["id", "expires_at", "fired_at", "completed_at"];
+
+    /// Create a mapping out of the columns of all roles, in the order of
+    /// `ROLES`. Roles without a column are `None`.
+    pub fn from_columns<S: Into<String>>(columns: Vec<Option<S>>) -> Result<Self, Box<dyn Error>> {
+        if columns.len() != Self::ROLES.len() {
+            return Err(format!("expected {} columns, got {}", Self::ROLES.len(), columns.len()).into());
+        }
+
+        let columns = columns
+            .into_iter()
+            .map(|column| column.map(Into::into))
+            .collect::<Vec<Option<String>>>();
+
+        for (role, column) in Self::ROLES.iter().zip(columns.iter()) {
+            if column.is_none() && Self::REQUIRED_ROLES.contains(role) {
+                return Err(format!("role \"{}\" must be mapped onto a column", role).into());
+            }
+        }
+
+        // Fields are initialized in the order they are written, which is the
+        // order of `ROLES`.
+        let mut columns = columns.into_iter();
+        let mut next = || columns.next().expect("the number of columns was checked");
+
+        let mapping = Self {
+            id: next().expect("required roles were checked"),
+            expires_at: next().expect("required roles were checked"),
+            fired_at: next().expect("required roles were checked"),
+            completed_at: next().expect("required roles were checked"),
+            attempts: next(),
+            last_error: next(),
+            repeat_every: next(),
+            schedule: next(),
+            repeat_until: next(),
+            max_occurrences: next(),
+            occurrences: next(),
+            missed_at: next(),
+            payload: next(),
+        };
+
+        // The maximum number of occurrences is checked against the number of
+        // occurrences so far.
+        if mapping.max_occurrences.is_some() && mapping.occurrences.is_none() {
+            return Err("role \"max_occurrences\" requires a column of role \"occurrences\"".into());
+        }
+
+        Ok(mapping)
+    }
+
+    /// The columns of all roles, in the order of `ROLES`.
+    pub fn columns(&self) -> [Option<&str>; 13] {
+        [
+            Some(&self.id),
+            Some(&self.expires_at),
+            Some(&self.fired_at),
+            Some(&self.completed_at),
+            self.attempts.as_ref(),
+            self.last_error.as_ref(),
+            self.repeat_every.as_ref(),
+            self.schedule.as_ref(),
+            self.repeat_until.as_ref(),
+            self.max_occurrences.as_ref(),
+            self.occurrences.as_ref(),
+            self.missed_at.as_ref(),
+            self.payload.as_ref(),
+        ]
+        .map(|column| column.map(String::as_str))
+    }
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self::from_columns(Self::ROLES.map(Some).to_vec()).expect("all roles are mapped")
+    }
+}
+
 /// Data for creating a timer from a row.
 #[derive(Copy, Clone)]
 pub struct CreateTimerFromRow {
     pub id: i64, // 8 bytes
     pub expires_at: chrono::DateTime<Local>, // 12 bytes
+    pub recurrence: Option<Recurrence>, // 56 bytes
 }
 
-impl<'a> TryFrom<&'a PgHeapTuple<'a, AllocatedByPostgres>> for CreateTimerFromRow {
+impl<'a, W: WhoAllocated> TryFrom<(&'a PgHeapTuple<'a, W>, &'a ColumnMapping)> for CreateTimerFromRow {
     type Error = Box<dyn Error>;
 
-    fn try_from(value: &'a PgHeapTuple<'a, AllocatedByPostgres>) -> Result<Self, Self::Error> {
+    fn try_from(value: (&'a PgHeapTuple<'a, W>, &'a ColumnMapping)) -> Result<Self, Self::Error> {
         let timer = TimerRow::try_from(value)?;
 
         if timer.fired_at.is_some() {
@@ -90,9 +351,14 @@ impl<'a> TryFrom<&'a PgHeapTuple<'a, AllocatedByPostgres>> for CreateTimerFromRo
             return Err("creating a completed timer is forbidden".into());
         }
 
+        if timer.missed_at.is_some() {
+            return Err("creating a missed timer is forbidden".into());
+        }
+
         Ok(Self {
             id: timer.id,
             expires_at: timer.expires_at,
+            recurrence: timer.recurrence,
         })
     }
 }
@@ -104,6 +370,10 @@ impl From<CreateTimerFromRow> for TimerRow {
             expires_at: value.expires_at,
             fired_at: None,
             completed_at: None,
+            attempts: 0,
+            recurrence: value.recurrence,
+            occurrences: 0,
+            missed_at: None,
         }
     }
 }
@@ -113,6 +383,467 @@ impl From<TimerRow> for CreateTimerFromRow {
         Self {
             id: value.id,
             expires_at: value.expires_at,
+            recurrence: value.recurrence,
+        }
+    }
+}
+
+/// The recurrence of a timer that is re-scheduled every time it fires.
+#[derive(Copy, Clone)]
+pub struct Recurrence {
+    // How the next expiration of the timer is computed.
+    pub repeat: Repeat,
+
+    // The timestamp after which the timer does not recur anymore.
+    pub until: Option<chrono::DateTime<Local>>,
+
+    // The maximum number of times the timer fires.
+    pub max_occurrences: Option<i32>,
+}
+
+/// The way a recurring timer is re-scheduled.
+#[derive(Copy, Clone)]
+pub enum Repeat {
+    /// The timer recurs at an interval after its previous expiration, which is
+    /// added like Postgres adds it to a timestamp with time zone.
+    Every(CalendarInterval),
+
+    /// The timer recurs at the occurrences of a cron schedule.
+    Schedule(CronSchedule),
+}
+
+impl Recurrence {
+    /// Create the recurrence of a timer out of the values of its columns.
+    ///
+    /// Returns None if the timer does not recur.
+    pub fn try_new(
+        every: Option<CalendarInterval>,
+        schedule: Option<CronSchedule>,
+        until: Option<chrono::DateTime<Local>>,
+        max_occurrences: Option<i32>,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        let repeat = match (every, schedule) {
+            (Some(_), Some(_)) => return Err("repeat_every and schedule are mutually exclusive".into()),
+            (Some(value), None) if value.months < 0 || value.days < 0 || value.micros < 0 => {
+                return Err("repeat_every must not have negative parts".into())
+            }
+            (Some(value), None) if value == CalendarInterval::default() => {
+                return Err("repeat_every must be positive".into())
+            }
+            (Some(value), None) => Repeat::Every(value),
+            (None, Some(value)) => Repeat::Schedule(value),
+            (None, None) if until.is_some() => return Err("repeat_until requires repeat_every or schedule".into()),
+            (None, None) if max_occurrences.is_some() => return Err("max_occurrences requires repeat_every or schedule".into()),
+            (None, None) => return Ok(None),
+        };
+
+        if matches!(max_occurrences, Some(value) if value < 1) {
+            return Err("max_occurrences must be positive".into());
+        }
+
+        Ok(Some(Self {
+            repeat,
+            until,
+            max_occurrences,
+        }))
+    }
+
+    /// Compute the next expiration of a timer that expired at `expires_at`
+    /// and has now fired `occurrences` times.
+    ///
+    /// Occurrences that have been missed in the meantime are skipped. Returns
+    /// None if the timer does not recur anymore.
+    pub fn next(
+        &self,
+        expires_at: chrono::DateTime<Local>,
+        occurrences: i32,
+        now: chrono::DateTime<Local>,
+    ) -> Option<chrono::DateTime<Local>> {
+        if matches!(self.max_occurrences, Some(max) if occurrences >= max) {
+            return None;
+        }
+
+        let next = match self.repeat {
+            // Intervals of elapsed time only skip missed occurrences at once.
+            Repeat::Every(every) if every.months == 0 && every.days == 0 => {
+                let every = every.micros;
+                let elapsed = (now - expires_at).num_microseconds()?.max(0);
+                expires_at + chrono::Duration::microseconds((elapsed / every + 1) * every)
+            }
+            // Calendar intervals move by at least a day, so missed occurrences
+            // are skipped one by one.
+            Repeat::Every(every) => {
+                let mut n = 1;
+
+                loop {
+                    let next = every.add_to(&expires_at, n)?;
+
+                    if next > now {
+                        break next;
+                    }
+
+                    n = n.checked_add(1)?;
+                }
+            }
+            Repeat::Schedule(schedule) => schedule.next_after(&now.max(expires_at))?,
+        };
+
+        match self.until {
+            Some(until) if next > until => None,
+            _ => Some(next),
         }
     }
 }
+
+/// The policy for retrying timers whose firing failed.
+#[derive(Copy, Clone)]
+pub struct RetryPolicy {
+    // The maximum number of attempts at firing a timer, including the first.
+    pub max_attempts: i32,
+
+    // The delay before the first retry, which doubles with every attempt.
+    pub backoff: chrono::Duration,
+
+    // The upper bound of the delay between retries.
+    pub backoff_max: chrono::Duration,
+
+    // The fraction of the delay by which retries are randomly spread out.
+    pub jitter: f64,
+
+    // Whether timers that have fired but have not been completed are fired
+    // again when the timer subsystem starts.
+    pub redeliver: bool,
+}
+
+impl RetryPolicy {
+    /// Compute the delay before the next attempt, given the number of failed
+    /// attempts so far.
+    pub fn delay(&self, attempts: i32) -> chrono::Duration {
+        let exponent = attempts.saturating_sub(1).clamp(0, 30) as u32;
+        let delay = self
+            .backoff
+            .num_milliseconds()
+            .saturating_mul(1 << exponent)
+            .min(self.backoff_max.num_milliseconds());
+
+        // A randomly seeded hasher is a good enough source of randomness for
+        // spreading out retries.
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        let factor = 1.0 + self.jitter * (2.0 * random - 1.0);
+
+        let millis = (delay as f64 * factor).max(0.0);
+
+        chrono::Duration::milliseconds(millis as i64)
+    }
+}
+
+/// The policy for timers that misfired, i.e. that are only armed once their
+/// expiration has long passed, e.g. because the server was down.
+#[derive(Copy, Clone)]
+pub struct MisfirePolicy {
+    // What happens to misfired timers.
+    pub action: MisfireAction,
+
+    // How long after its expiration an armed timer counts as misfired, added
+    // to the expiration like Postgres adds an interval to a timestamp.
+    pub threshold: CalendarInterval,
+}
+
+/// What happens to misfired timers.
+#[derive(Copy, Clone, PartialEq)]
+pub enum MisfireAction {
+    /// All misfired timers are fired right away.
+    FireAll,
+
+    /// Only the latest misfired timer per key is fired, the others are marked
+    /// as missed.
+    FireLatest,
+
+    /// Misfired timers are marked as missed without being fired.
+    Skip,
+
+    /// Misfired timers are fired at a limited rate, in timers per second.
+    CatchUp(f64),
+}
+
+impl MisfirePolicy {
+    /// Create a misfire policy out of the values of its columns.
+    pub fn try_new(
+        action: &str,
+        rate: Option<f64>,
+        threshold: CalendarInterval,
+    ) -> Result<Self, Box<dyn Error>> {
+        if threshold.months < 0 || threshold.days < 0 || threshold.micros < 0 {
+            return Err("threshold must not have negative parts".into());
+        }
+
+        let action = match (action, rate) {
+            ("fire_all", None) => MisfireAction::FireAll,
+            ("fire_latest", None) => MisfireAction::FireLatest,
+            ("skip", None) => MisfireAction::Skip,
+            ("catch_up", Some(rate)) if rate > 0.0 => MisfireAction::CatchUp(rate),
+            ("catch_up", _) => return Err("catch_up requires a positive rate".into()),
+            (_, Some(_)) => return Err("only catch_up takes a rate".into()),
+            (action, None) => return Err(format!("unknown misfire policy \"{}\"", action).into()),
+        };
+
+        Ok(Self { action, threshold })
+    }
+
+    /// Whether a timer that is armed at `now` has misfired.
+    pub fn is_misfired(&self, expires_at: chrono::DateTime<Local>, now: chrono::DateTime<Local>) -> bool {
+        self.threshold
+            .add_to(&expires_at, 1)
+            .is_some_and(|misfired_at| misfired_at < now)
+    }
+}
+
+impl Default for MisfirePolicy {
+    fn default() -> Self {
+        Self {
+            action: MisfireAction::FireAll,
+            threshold: CalendarInterval {
+                months: 0,
+                days: 0,
+                micros: 60_000_000,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 3_600_000_000;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn every(months: i32, days: i32, micros: i64) -> Option<CalendarInterval> {
+        Some(CalendarInterval { months, days, micros })
+    }
+
+    fn recurrence(
+        every: Option<CalendarInterval>,
+        schedule: Option<&str>,
+        until: Option<DateTime<Local>>,
+        max_occurrences: Option<i32>,
+    ) -> Result<Option<Recurrence>, Box<dyn Error>> {
+        let schedule = schedule.map(|value| CronSchedule::parse(value).unwrap());
+
+        Recurrence::try_new(every, schedule, until, max_occurrences)
+    }
+
+    #[test]
+    fn test_recurrence_try_new() {
+        assert!(recurrence(None, None, None, None).unwrap().is_none());
+        assert!(recurrence(every(0, 0, HOUR), None, None, None).unwrap().is_some());
+        assert!(recurrence(None, Some("@daily"), None, Some(1)).unwrap().is_some());
+
+        assert!(recurrence(every(0, 0, HOUR), Some("@daily"), None, None).is_err());
+        assert!(recurrence(every(0, 0, 0), None, None, None).is_err());
+        assert!(recurrence(every(1, -1, 0), None, None, None).is_err());
+        assert!(recurrence(every(0, 0, -HOUR), None, None, None).is_err());
+        assert!(recurrence(None, None, Some(local(2023, 6, 1, 9, 0)), None).is_err());
+        assert!(recurrence(None, None, None, Some(3)).is_err());
+        assert!(recurrence(every(0, 0, HOUR), None, None, Some(0)).is_err());
+    }
+
+    #[test]
+    fn test_recurrence_next_every() {
+        let hourly = recurrence(every(0, 0, HOUR), None, None, None).unwrap().unwrap();
+        let expires_at = local(2023, 6, 1, 9, 0);
+
+        assert_eq!(hourly.next(expires_at, 1, expires_at), Some(local(2023, 6, 1, 10, 0)));
+
+        // Missed occurrences are skipped.
+        let now = local(2023, 6, 1, 11, 30);
+        assert_eq!(hourly.next(expires_at, 1, now), Some(local(2023, 6, 1, 12, 0)));
+    }
+
+    #[test]
+    fn test_recurrence_next_calendar() {
+        // Months stay on the day of month of the previous expiration, as far
+        // as the month allows.
+        let monthly = recurrence(every(1, 0, 0), None, None, None).unwrap().unwrap();
+        let expires_at = local(2023, 1, 31, 9, 0);
+
+        assert_eq!(monthly.next(expires_at, 1, expires_at), Some(local(2023, 2, 28, 9, 0)));
+
+        // Missed occurrences are skipped, without accumulating the clamping.
+        let now = local(2023, 4, 15, 0, 0);
+        assert_eq!(monthly.next(expires_at, 1, now), Some(local(2023, 4, 30, 9, 0)));
+
+        let weekly = recurrence(every(0, 7, 0), None, None, None).unwrap().unwrap();
+        let expires_at = local(2023, 6, 1, 9, 0);
+        assert_eq!(weekly.next(expires_at, 1, expires_at), Some(local(2023, 6, 8, 9, 0)));
+    }
+
+    #[test]
+    fn test_recurrence_next_schedule() {
+        let daily = recurrence(None, Some("0 9 * * *"), None, None).unwrap().unwrap();
+        let expires_at = local(2023, 6, 1, 9, 0);
+
+        assert_eq!(daily.next(expires_at, 1, expires_at), Some(local(2023, 6, 2, 9, 0)));
+
+        // Missed occurrences are skipped.
+        let now = local(2023, 6, 3, 12, 0);
+        assert_eq!(daily.next(expires_at, 1, now), Some(local(2023, 6, 4, 9, 0)));
+    }
+
+    #[test]
+    fn test_recurrence_next_limits() {
+        let expires_at = local(2023, 6, 1, 9, 0);
+
+        let limited = recurrence(every(0, 0, HOUR), None, None, Some(3)).unwrap().unwrap();
+        assert_eq!(limited.next(expires_at, 2, expires_at), Some(local(2023, 6, 1, 10, 0)));
+        assert_eq!(limited.next(expires_at, 3, expires_at), None);
+
+        let until = Some(local(2023, 6, 1, 10, 30));
+        let bounded = recurrence(every(0, 0, HOUR), None, until, None).unwrap().unwrap();
+        assert_eq!(bounded.next(expires_at, 1, expires_at), Some(local(2023, 6, 1, 10, 0)));
+        assert_eq!(bounded.next(local(2023, 6, 1, 10, 0), 2, expires_at), None);
+    }
+
+    #[test]
+    fn test_column_mapping_default() {
+        let mapping = ColumnMapping::default();
+
+        assert_eq!(mapping.columns(), ColumnMapping::ROLES.map(Some));
+    }
+
+    #[test]
+    fn test_column_mapping_from_columns() {
+        let mut columns = ColumnMapping::ROLES.map(|_| None).to_vec();
+        columns[0] = Some("order_id");
+        columns[1] = Some("payment_deadline");
+        columns[2] = Some("fired_at");
+        columns[3] = Some("completed_at");
+        columns[12] = Some("details");
+
+        let mapping = ColumnMapping::from_columns(columns.clone()).unwrap();
+
+        assert_eq!(mapping.id, "order_id");
+        assert_eq!(mapping.expires_at, "payment_deadline");
+        assert_eq!(mapping.attempts, None);
+        assert_eq!(mapping.missed_at, None);
+        assert_eq!(mapping.payload.as_deref(), Some("details"));
+        assert_eq!(mapping.columns().to_vec(), columns);
+    }
+
+    #[test]
+    fn test_column_mapping_from_columns_invalid() {
+        // The number of columns must match the number of roles.
+        assert!(ColumnMapping::from_columns(ColumnMapping::ROLES[..12].iter().copied().map(Some).collect()).is_err());
+        assert!(ColumnMapping::from_columns(Vec::<Option<&str>>::new()).is_err());
+
+        // Required roles must be mapped.
+        for i in 0..ColumnMapping::REQUIRED_ROLES.len() {
+            let mut columns = ColumnMapping::ROLES.map(Some).to_vec();
+            columns[i] = None;
+
+            assert!(ColumnMapping::from_columns(columns).is_err());
+        }
+
+        // The maximum number of occurrences requires the occurrences.
+        let mut columns = ColumnMapping::ROLES.map(Some).to_vec();
+        columns[10] = None;
+        assert!(ColumnMapping::from_columns(columns.clone()).is_err());
+
+        columns[9] = None;
+        assert!(ColumnMapping::from_columns(columns).is_ok());
+    }
+
+    fn retry_policy(backoff_ms: i64, backoff_max_ms: i64, jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            backoff: chrono::Duration::milliseconds(backoff_ms),
+            backoff_max: chrono::Duration::milliseconds(backoff_max_ms),
+            jitter,
+            redeliver: false,
+        }
+    }
+
+    #[test]
+    fn test_retry_policy_delay() {
+        let policy = retry_policy(1000, 10_000, 0.0);
+
+        assert_eq!(policy.delay(0), chrono::Duration::seconds(1));
+        assert_eq!(policy.delay(1), chrono::Duration::seconds(1));
+        assert_eq!(policy.delay(2), chrono::Duration::seconds(2));
+        assert_eq!(policy.delay(3), chrono::Duration::seconds(4));
+        assert_eq!(policy.delay(4), chrono::Duration::seconds(8));
+
+        // The delay stops doubling at the maximum, without overflowing.
+        assert_eq!(policy.delay(5), chrono::Duration::seconds(10));
+        assert_eq!(policy.delay(i32::MAX), chrono::Duration::seconds(10));
+    }
+
+    #[test]
+    fn test_retry_policy_delay_jitter() {
+        let policy = retry_policy(1000, 10_000, 0.5);
+
+        for attempts in 1..=5 {
+            let delay = policy.delay(attempts).num_milliseconds();
+            let expected = (1000 << (attempts - 1)).min(10_000);
+
+            assert!(delay >= expected / 2 && delay <= expected * 3 / 2, "{}", delay);
+        }
+
+        // Full jitter spreads retries out down to no delay at all.
+        let policy = retry_policy(1000, 10_000, 1.0);
+
+        for _ in 0..100 {
+            let delay = policy.delay(1).num_milliseconds();
+
+            assert!((0..=2000).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn test_misfire_policy_try_new() {
+        let threshold = every(0, 0, 5 * 60_000_000).unwrap();
+
+        let policy = MisfirePolicy::try_new("fire_all", None, threshold).unwrap();
+        assert!(policy.action == MisfireAction::FireAll);
+        assert_eq!(policy.threshold, threshold);
+
+        let policy = MisfirePolicy::try_new("fire_latest", None, threshold).unwrap();
+        assert!(policy.action == MisfireAction::FireLatest);
+
+        let policy = MisfirePolicy::try_new("skip", None, threshold).unwrap();
+        assert!(policy.action == MisfireAction::Skip);
+
+        let policy = MisfirePolicy::try_new("catch_up", Some(2.5), threshold).unwrap();
+        assert!(policy.action == MisfireAction::CatchUp(2.5));
+
+        // Only catch_up takes a rate, which must be positive.
+        assert!(MisfirePolicy::try_new("catch_up", None, threshold).is_err());
+        assert!(MisfirePolicy::try_new("catch_up", Some(0.0), threshold).is_err());
+        assert!(MisfirePolicy::try_new("catch_up", Some(-1.0), threshold).is_err());
+        assert!(MisfirePolicy::try_new("skip", Some(1.0), threshold).is_err());
+
+        assert!(MisfirePolicy::try_new("fire_some", None, threshold).is_err());
+
+        assert!(MisfirePolicy::try_new("fire_all", None, every(1, -1, 0).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_misfire_policy_is_misfired() {
+        let policy = MisfirePolicy::default();
+        let expires_at = local(2023, 6, 1, 9, 0);
+
+        assert!(!policy.is_misfired(expires_at, expires_at));
+        assert!(!policy.is_misfired(expires_at, local(2023, 6, 1, 9, 1)));
+        assert!(policy.is_misfired(expires_at, local(2023, 6, 1, 9, 2)));
+
+        // Months are calendar months, not 30 days.
+        let policy = MisfirePolicy::try_new("fire_all", None, every(1, 0, 0).unwrap()).unwrap();
+        let expires_at = local(2023, 2, 1, 9, 0);
+
+        assert!(!policy.is_misfired(expires_at, local(2023, 3, 1, 9, 0)));
+        assert!(policy.is_misfired(expires_at, local(2023, 3, 1, 9, 1)));
+    }
+}
@@ -1,59 +1,162 @@
 // src/worker.rs
 
-use heapless::mpmc::MpMcQueue;
+use chrono::prelude::*;
+use heapless::FnvIndexMap;
 
 use pgrx::bgworkers::*;
 use pgrx::log;
+use pgrx::lwlock::PgLwLock;
 use pgrx::pg_shmem_init;
 use pgrx::pg_sys::Oid;
 use pgrx::prelude::*;
 use pgrx::shmem::*;
 
-use tokio::time;
-use tokio::time::MissedTickBehavior;
-
+use std::fmt;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
 use std::time::Duration as StdDuration;
 
 use crate::commands;
 use crate::commands::TimerTableData;
 use crate::config;
+use crate::launcher::DatabaseSlots;
+use crate::shmem::SharedLatches;
 use crate::shmem::SharedObject;
+use crate::shmem::SharedQueue;
+use crate::stats;
+use crate::subtransaction;
+use crate::timer;
+use crate::types::MisfireAction;
+use crate::types::TimerId;
 use crate::types::TimerRow;
 
+/// The shortest delay before a failed timer is retried. The retry is stored as
+/// the new expiration of the timer, which must still be in the future when the
+/// row is updated.
+const RETRY_MIN_DELAY: StdDuration = StdDuration::from_secs(1);
+
 /// Initialize the workers subsystem.
 pub(crate) fn pg_init() {
     log!("quartz-workers: pg_init");
 
-    pg_shmem_init!(WORKER_QUEUE);
+    pg_shmem_init!(WORKER_QUEUES);
+    pg_shmem_init!(WORKER_LATCHES);
+    pg_shmem_init!(FIRING_TIMERS);
+    pg_shmem_init!(IN_FLIGHT_TIMERS);
+}
 
-    let worker_count = match std::thread::available_parallelism() {
-        Ok(value) => value.get() / 2, // fixme??
-        Err(e) => {
-            warning!("quartz-workers: pg_init failed to determine available parallelism (error: {}), using 1", e);
+/// The workers of a database, which are terminated when this is dropped.
+pub(crate) struct Workers(Vec<DynamicBackgroundWorker>);
 
-            1
+impl Drop for Workers {
+    fn drop(&mut self) {
+        for worker in self.0.drain(..) {
+            worker.terminate();
         }
-    };
+    }
+}
+
+/// Start the workers of a database.
+///
+/// This must be called by the timer subsystem of the database. Workers exit
+/// once the timer subsystem that started them is no longer running, e.g.
+/// because it exited without terminating them.
+pub(crate) fn start(database: &str) -> Workers {
+    let worker_count = config::worker_count();
+
+    let mut workers = Vec::with_capacity(worker_count);
 
     for i in 0..worker_count {
-        BackgroundWorkerBuilder::new(format!("quartz-worker-{}", i).as_str())
+        let worker = BackgroundWorkerBuilder::new(format!("quartz-worker-{} ({})", i, database).as_str())
             .set_library("quartz")
             .set_function("quartz_worker_main")
             .set_argument((i as i32).into_datum()) // worker ID
+            .set_extra(database)
             .set_type("quartz-worker")
             .set_start_time(BgWorkerStartTime::RecoveryFinished)
-            .set_restart_time(StdDuration::from_secs(1).into())
+            .set_restart_time(config::restart_time())
+            .set_notify_pid(unsafe { pg_sys::MyProcPid })
             .enable_shmem_access(None)
             .enable_spi_access()
-            .load();
+            .load_dynamic();
+
+        workers.push(worker);
     }
+
+    Workers(workers)
 }
 
-type WorkerEventsQueueType = MpMcQueue<WorkerSubsystemEvent, 128>;
+type WorkerEventsQueueType = SharedQueue<WorkerSubsystemEvent, { config::MAX_QUEUE_SIZE }>;
+
+/// The shared queues used for communicating events to the workers subsystems,
+/// one per database slot.
+static WORKER_QUEUES: SharedObject<[WorkerEventsQueueType; config::MAX_DATABASES]> =
+    SharedObject::new("quartz-workers-queues");
+
+/// The latches of the workers, by worker ID, which are set when events are
+/// enqueued for them.
+static WORKER_LATCHES: SharedObject<[SharedLatches<{ config::MAX_WORKERS }>; config::MAX_DATABASES]> =
+    SharedObject::new("quartz-workers-latches");
+
+/// The type of the set of timers that are currently being fired by workers.
+///
+/// The capacity leaves room for a full worker queue plus the events that are
+/// being processed by the workers themselves.
+type FiringTimersType = FnvIndexMap<(u32, i64), (), { 2 * config::MAX_QUEUE_SIZE }>;
+
+/// The timers that have been handed over to the workers subsystems and have
+/// not been processed yet, as (table OID, timer ID) pairs per database slot.
+///
+/// OIDs are stored as their raw value, since heapless needs hash32 keys.
+#[derive(Default)]
+struct FiringTimers([FiringTimersType; config::MAX_DATABASES]);
+
+unsafe impl PGRXSharedMemory for FiringTimers {}
 
-/// The shared queue used for communicating events to the workers subsystem.
-static WORKER_QUEUE: SharedObject<WorkerEventsQueueType> =
-    SharedObject::new("quartz-workers-queue");
+static FIRING_TIMERS: PgLwLock<FiringTimers> = PgLwLock::new();
+
+/// The timer that a worker is firing, so that it can be fired again if the
+/// worker exits before it is done.
+#[derive(Default)]
+struct InFlightTimer {
+    // The OID of the timers table, or 0 if no timer is in flight.
+    table_oid: AtomicU32,
+    timer_id: AtomicI64,
+}
+
+impl InFlightTimer {
+    fn get(&self) -> Option<(Oid, i64)> {
+        match self.table_oid.load(Ordering::Acquire) {
+            0 => None,
+            // Only OIDs of timers tables are ever stored.
+            oid => Some((unsafe { Oid::from_u32_unchecked(oid) }, self.timer_id.load(Ordering::Acquire))),
+        }
+    }
+
+    fn set(&self, table_oid: Oid, timer_id: i64) {
+        self.timer_id.store(timer_id, Ordering::Release);
+        self.table_oid.store(table_oid.as_u32(), Ordering::Release);
+    }
+
+    fn clear(&self) {
+        self.table_oid.store(0, Ordering::Release);
+    }
+}
+
+/// The in-flight timers of the workers of a database, by worker ID.
+struct InFlightTimers([InFlightTimer; config::MAX_WORKERS]);
+
+impl Default for InFlightTimers {
+    fn default() -> Self {
+        Self(std::array::from_fn(|_| InFlightTimer::default()))
+    }
+}
+
+/// The in-flight timers of the workers, per database slot. They outlive the
+/// workers, so that a restarted worker picks up where it left off.
+static IN_FLIGHT_TIMERS: SharedObject<[InFlightTimers; config::MAX_DATABASES]> =
+    SharedObject::new("quartz-workers-in-flight-timers");
 
 /// WorkerEvent is an event that can be sent to the workers subsystem.
 pub enum WorkerSubsystemEvent {
@@ -61,26 +164,92 @@ pub enum WorkerSubsystemEvent {
 }
 
 /// TimerFiredEvent is an event that is sent to a worker when a timer fires.
+///
+/// Events are passed through shared memory, so they do not carry the payload
+/// of the timer. Workers read it from the row when they fire the timer.
 pub struct TimerFiredEvent {
     pub table_oid: Oid,
     pub row: TimerRow,
+    /// Whether the timer misfired, in which case the misfire policy of its
+    /// table decides whether it is fired.
+    pub misfired: bool,
 }
 
-/// WorkersHandle is a handle used for interacting with the workers subsystem.
+/// WorkersHandle is a handle used for interacting with the workers subsystem
+/// of the current database.
 ///
 /// WorkersHandle is not usable prior to the initialization of the workers
 /// subsystem.
 #[derive(Clone, Copy)]
-pub struct WorkersHandle(());
+pub struct WorkersHandle {
+    // The database slot of the subsystem, if the database has one.
+    slot: Option<usize>,
+}
 
 impl WorkersHandle {
     pub fn get() -> Self {
-        Self(())
+        Self {
+            slot: DatabaseSlots::get().find(unsafe { pg_sys::MyDatabaseId }),
+        }
     }
 
     /// Enqueue an event to be processed by the workers subsystem.
+    ///
+    /// Fired timers are marked as firing until a worker has processed them.
     pub fn enqueue_event(&self, event: WorkerSubsystemEvent) -> bool {
-        WORKER_QUEUE.get().enqueue(event).is_ok()
+        let slot = if let Some(value) = self.slot {
+            value
+        } else {
+            return false;
+        };
+
+        let timer_id = match &event {
+            WorkerSubsystemEvent::TimerFired(event) => TimerId::new(event.table_oid, event.row.id),
+        };
+
+        let key = (timer_id.table_oid.as_u32(), timer_id.id);
+
+        if FIRING_TIMERS.exclusive().0[slot].insert(key, ()).is_err() {
+            warning!(
+                "quartz-workers: timer {} cannot be marked as firing: too many timers are firing",
+                timer_id
+            );
+        }
+
+        let limit = config::WORKER_QUEUE_SIZE.get() as usize;
+
+        if WORKER_QUEUES.get()[slot].enqueue(event, limit).is_err() {
+            FIRING_TIMERS.exclusive().0[slot].remove(&key);
+            stats::database(slot).worker_queue_overflows.incr();
+
+            return false;
+        }
+
+        WORKER_LATCHES.get()[slot].set_all();
+
+        true
+    }
+
+    /// The number of events in the queue of the workers subsystem, if the
+    /// database has one.
+    pub fn queue_depth(&self) -> Option<usize> {
+        self.slot.map(|slot| WORKER_QUEUES.get()[slot].depth())
+    }
+
+    /// Check whether a timer is currently being fired by the workers
+    /// subsystem.
+    pub fn is_timer_firing(&self, table_oid: Oid, timer_id: i64) -> bool {
+        match self.slot {
+            Some(slot) => FIRING_TIMERS.share().0[slot].contains_key(&(table_oid.as_u32(), timer_id)),
+            None => false,
+        }
+    }
+
+    /// Mark a timer as no longer being fired.
+    fn finish_firing(&self, table_oid: Oid, timer_id: i64) {
+        if let Some(slot) = self.slot {
+            FIRING_TIMERS.exclusive().0[slot].remove(&(table_oid.as_u32(), timer_id));
+        }
     }
 }
 
@@ -88,51 +257,221 @@ impl WorkersHandle {
 #[no_mangle]
 pub extern "C" fn quartz_worker_main(arg: pg_sys::Datum) {
     let worker_id = unsafe { i32::from_datum(arg, false) }.unwrap();
+    let database = BackgroundWorker::get_extra();
 
-    log!("quartz-worker-{}: starting", worker_id);
+    log!("quartz-worker-{}: starting for database \"{}\"", worker_id, database);
 
     BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGHUP | SignalWakeFlags::SIGTERM);
-    BackgroundWorker::connect_worker_to_spi(config::SPI_DATABASE_NAME, config::SPI_USER_NAME);
+    BackgroundWorker::connect_worker_to_spi(Some(database), config::role().as_deref());
 
-    let mut worker = Worker::new(worker_id, WORKER_QUEUE.get());
+    // The timer subsystem claims the slot of the database before starting
+    // the workers.
+    let handle = WorkersHandle::get();
 
-    let runtime = tokio::runtime::Builder::new_current_thread()
-        .enable_time()
-        // .event_interval(42)
-        .max_blocking_threads(1)
-        .build()
-        .unwrap();
+    let slot = if let Some(value) = handle.slot {
+        value
+    } else {
+        error!("quartz-worker-{}: database \"{}\" has no timer subsystem", worker_id, database);
+    };
 
-    runtime.block_on(worker.run())
+    // The timer subsystem that started the worker is notified of its state.
+    // Workers of a previous timer subsystem must not run alongside the ones
+    // of the current one, since they share their IDs.
+    let timer_pid = unsafe { (*pg_sys::MyBgworkerEntry).bgw_notify_pid };
+
+    if timer::timer_pid(slot) != Some(timer_pid) {
+        log!("quartz-worker-{}: the timer subsystem that started it is gone", worker_id);
+
+        return;
+    }
+
+    let latches = &WORKER_LATCHES.get()[slot];
+    latches.attach(worker_id as usize);
+
+    let mut worker = Worker::new(
+        worker_id,
+        timer_pid,
+        &WORKER_QUEUES.get()[slot],
+        &IN_FLIGHT_TIMERS.get()[slot].0[worker_id as usize],
+        handle,
+    );
+
+    worker.recover_in_flight_timer();
+    worker.run();
+
+    latches.detach(worker_id as usize);
 }
 
-pub(self) struct Worker {
+/// The outcome of processing a fired timer that is still pending.
+#[derive(Clone, Copy)]
+enum FireOutcome {
+    // The timer fired.
+    Fired,
+
+    // The timer fired again, since its action had not been completed.
+    Redelivered,
+
+    // The timer misfired and was skipped by its table's misfire policy.
+    Missed,
+}
+
+impl fmt::Display for FireOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FireOutcome::Fired => write!(f, "fired"),
+            FireOutcome::Redelivered => write!(f, "redelivered"),
+            FireOutcome::Missed => write!(f, "missed"),
+        }
+    }
+}
+
+struct Worker {
     worker_id: i32,
+    // The PID of the timer subsystem that started the worker.
+    timer_pid: i32,
     queue: &'static WorkerEventsQueueType,
+    in_flight: &'static InFlightTimer,
+    handle: WorkersHandle,
 }
 
 impl Worker {
-    pub fn new(worker_id: i32, queue: &'static WorkerEventsQueueType) -> Self {
-        Self { worker_id, queue }
+    pub fn new(
+        worker_id: i32,
+        timer_pid: i32,
+        queue: &'static WorkerEventsQueueType,
+        in_flight: &'static InFlightTimer,
+        handle: WorkersHandle,
+    ) -> Self {
+        Self {
+            worker_id,
+            timer_pid,
+            queue,
+            in_flight,
+            handle,
+        }
     }
 
-    async fn run(&mut self) {
-        let mut poll_term_interval = time::interval(StdDuration::from_secs(1));
-        poll_term_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    /// Fire the timer again that was in flight when this worker last exited,
+    /// e.g. because firing it raised an error outside of the subtransaction.
+    ///
+    /// Every such exit counts as a failed attempt, so that a timer that keeps
+    /// taking its worker down is eventually given up on.
+    fn recover_in_flight_timer(&mut self) {
+        let (table_oid, timer_id) = if let Some(value) = self.in_flight.get() {
+            value
+        } else {
+            return;
+        };
 
-        let mut poll_timers_interval = time::interval(StdDuration::from_millis(1));
-        poll_timers_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let worker_id = self.worker_id;
 
-        loop {
-            tokio::select! {
-                _ = poll_term_interval.tick() => {
-                    if !self.on_poll_term() {
-                        break;
-                    }
+        let result: Result<Option<TimerRow>, spi::Error> = BackgroundWorker::transaction(|| {
+            Spi::connect(|mut client| {
+                let TimerTableData {
+                    schema,
+                    table,
+                    retry_policy,
+                    columns,
+                    enabled,
+                    ..
+                } = match commands::find_timer_table(&client, table_oid)? {
+                    Some(value) => value,
+                    None => return Ok(None),
+                };
+
+                // The timer is armed again when its table is activated.
+                if !enabled {
+                    return Ok(None);
                 }
-                _ = poll_timers_interval.tick() => {
-                    self.on_poll_events();
+
+                let row = match commands::find_timer(&client, schema.as_str(), table.as_str(), &columns, timer_id)? {
+                    Some(value) => value,
+                    None => return Ok(None),
+                };
+
+                // Timers that have been rescheduled since are armed again by
+                // the timer subsystem.
+                let due = match (row.fired_at, row.completed_at) {
+                    (None, _) => row.missed_at.is_none() && row.expires_at <= Local::now(),
+                    (Some(_), None) => retry_policy.redeliver,
+                    _ => false,
+                };
+
+                if !due {
+                    return Ok(None);
                 }
+
+                let attempts = match commands::record_timer_failure(
+                    &mut client,
+                    schema.as_str(),
+                    table.as_str(),
+                    &columns,
+                    timer_id,
+                    "worker exited while firing the timer",
+                )? {
+                    Some(value) => value,
+                    None => return Ok(None),
+                };
+
+                if attempts >= retry_policy.max_attempts {
+                    warning!(
+                        "quartz-worker-{}: timer {} was in flight when the worker exited (attempt {} of {}), giving up",
+                        worker_id,
+                        TimerId::new(table_oid, timer_id),
+                        attempts,
+                        retry_policy.max_attempts
+                    );
+
+                    return Ok(None);
+                }
+
+                warning!(
+                    "quartz-worker-{}: timer {} was in flight when the worker exited (attempt {} of {}), firing it again",
+                    worker_id,
+                    TimerId::new(table_oid, timer_id),
+                    attempts,
+                    retry_policy.max_attempts
+                );
+
+                Ok(Some(TimerRow { attempts, ..row }))
+            })
+        });
+
+        match result {
+            Ok(Some(row)) => self.process_timer_fired(TimerFiredEvent {
+                table_oid,
+                row,
+                misfired: false,
+            }),
+            Ok(None) => {
+                self.in_flight.clear();
+                self.handle.finish_firing(table_oid, timer_id);
+            }
+            Err(e) => {
+                error!(
+                    "quartz-worker-{}: recover timer {} in flight: {}",
+                    worker_id,
+                    TimerId::new(table_oid, timer_id),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Run the worker.
+    ///
+    /// The worker sleeps on its latch until events are enqueued, and then
+    /// processes events until the queue is empty.
+    fn run(&mut self) {
+        loop {
+            if !self.on_poll_term() {
+                break;
+            }
+
+            self.on_poll_events();
+
+            if !BackgroundWorker::wait_latch(Some(StdDuration::from_secs(1))) {
+                break;
             }
         }
 
@@ -151,53 +490,345 @@ impl Worker {
             // on SIGHUP, you might want to reload some external configuration or something
         }
 
-        return true;
+        // A restarted timer subsystem starts workers of its own.
+        if self.handle.slot.and_then(timer::timer_pid) != Some(self.timer_pid) {
+            log!("quartz-worker-{}: the timer subsystem that started it is gone", self.worker_id);
+
+            return false;
+        }
+
+        true
     }
 
     fn on_poll_events(&mut self) {
-        let event = if let Some(value) = self.queue.dequeue() {
-            value
-        } else {
-            return;
-        };
-
-        use WorkerSubsystemEvent::*;
-        match event {
-            TimerFired(event) => self.process_timer_fired(event),
+        while !BackgroundWorker::sigterm_received() {
+            let event = if let Some(value) = self.queue.dequeue() {
+                value
+            } else {
+                return;
+            };
+
+            use WorkerSubsystemEvent::*;
+            match event {
+                TimerFired(event) => self.process_timer_fired(event),
+            }
         }
     }
 
     fn process_timer_fired(&mut self, event: TimerFiredEvent) {
-        let TimerFiredEvent { table_oid, row } = event;
+        let TimerFiredEvent {
+            table_oid,
+            row,
+            misfired,
+        } = event;
+        let worker_id = self.worker_id;
+
+        // Timers that have fired before are redelivered, since their action
+        // has not been completed.
+        let redelivery = row.fired_at.is_some();
+        let stats = self
+            .handle
+            .slot
+            .and_then(|slot| stats::database(slot).table(table_oid));
+
+        self.in_flight.set(table_oid, row.id);
+
+        let result: Result<(), spi::Error> =
+            BackgroundWorker::transaction(|| {
+                let TimerTableData {
+                    schema,
+                    table,
+                    callback,
+                    retry_policy,
+                    notify_channel,
+                    misfire_policy,
+                    misfire_key,
+                    columns,
+                    enabled,
+                    owner,
+                    ..
+                } = match Spi::connect(|client| commands::find_timer_table(&client, table_oid))? {
+                    Some(value) => value,
+                    None => {
+                        warning!(
+                            "quartz-worker-{}: timer {} is no longer in a timers table",
+                            worker_id,
+                            TimerId::new(table_oid, row.id)
+                        );
+
+                        return Ok(());
+                    }
+                };
 
-        let result: Result<(), spi::Error> = BackgroundWorker::transaction(|| {
-            Spi::connect(|mut client| {
-                let TimerTableData { schema, table, .. } =
-                    commands::find_timer_table(&client, table_oid)?.expect("Timer table not found");
+                if !enabled {
+                    warning!(
+                        "quartz-worker-{}: timer {} is in a deactivated timers table",
+                        worker_id,
+                        TimerId::new(table_oid, row.id)
+                    );
 
-                commands::mark_timer_as_fired(
-                    &mut client,
-                    schema.as_str(),
-                    table.as_str(),
-                    row.id,
-                )?;
+                    return Ok(());
+                }
 
-                log!(
-                    "quartz-worker-{}: timer {} in \"{}\".\"{}\" fired",
-                    self.worker_id,
-                    row.id,
-                    schema,
-                    table
-                );
+                // Firing happens in a subtransaction, so that a failure can be
+                // recorded on the row and the timer can be retried later.
+                let fired = subtransaction::run(|| {
+                    Spi::connect(|mut client| {
+                        let timer = match commands::find_timer_for_update(
+                            &mut client,
+                            schema.as_str(),
+                            table.as_str(),
+                            &columns,
+                            row.id,
+                        )? {
+                            Some(value) => value,
+                            None => return Ok(None),
+                        };
+
+                        if redelivery {
+                            // A redelivered timer stays fired, and is only
+                            // delivered again until it is completed.
+                            if timer.fired_at.is_none() || timer.completed_at.is_some() {
+                                return Ok(None);
+                            }
+                        } else {
+                            if timer.fired_at.is_some() || timer.missed_at.is_some() {
+                                return Ok(None);
+                            }
+
+                            let now = Local::now();
+
+                            let skip = misfired
+                                && match misfire_policy.action {
+                                    MisfireAction::Skip => true,
+                                    MisfireAction::FireLatest => commands::has_later_misfired_timer(
+                                        &client,
+                                        schema.as_str(),
+                                        table.as_str(),
+                                        &columns,
+                                        misfire_key.as_deref(),
+                                        row.id,
+                                        misfire_policy.threshold,
+                                    )?,
+                                    MisfireAction::FireAll | MisfireAction::CatchUp(_) => false,
+                                };
+
+                            // Skipped recurring timers are moved to their next
+                            // expiration, without counting the missed one.
+                            if skip {
+                                let next_expires_at = timer.recurrence.and_then(|recurrence| {
+                                    recurrence.next(timer.expires_at, timer.occurrences, now)
+                                });
+
+                                match next_expires_at {
+                                    Some(next_expires_at) => commands::skip_recurring_timer(
+                                        &mut client,
+                                        schema.as_str(),
+                                        table.as_str(),
+                                        &columns,
+                                        row.id,
+                                        next_expires_at,
+                                    )?,
+                                    None => commands::mark_timer_as_missed(
+                                        &mut client,
+                                        schema.as_str(),
+                                        table.as_str(),
+                                        &columns,
+                                        row.id,
+                                    )?,
+                                }
+
+                                return Ok(Some(FireOutcome::Missed));
+                            }
+
+                            let next_expires_at = timer.recurrence.and_then(|recurrence| {
+                                recurrence.next(timer.expires_at, timer.occurrences + 1, now)
+                            });
+
+                            // Recurring timers are moved to their next
+                            // expiration and are only marked as fired after
+                            // the last one.
+                            match next_expires_at {
+                                Some(next_expires_at) => commands::advance_recurring_timer(
+                                    &mut client,
+                                    schema.as_str(),
+                                    table.as_str(),
+                                    &columns,
+                                    row.id,
+                                    next_expires_at,
+                                )?,
+                                None => {
+                                    commands::mark_timer_as_fired(
+                                        &mut client,
+                                        schema.as_str(),
+                                        table.as_str(),
+                                        &columns,
+                                        row.id,
+                                    )?;
+                                }
+                            }
+                        }
+
+                        if let Some(channel) = &notify_channel {
+                            commands::notify_timer_fired(
+                                &mut client,
+                                channel.as_str(),
+                                schema.as_str(),
+                                table.as_str(),
+                                &columns,
+                                TimerId::new(table_oid, row.id),
+                                timer.expires_at,
+                            )?;
+                        }
+
+                        if let Some(callback) = &callback {
+                            commands::invoke_timer_callback(
+                                &mut client,
+                                schema.as_str(),
+                                table.as_str(),
+                                &columns,
+                                callback.as_str(),
+                                owner,
+                                row.id,
+                            )?;
+                        }
+
+                        Ok(Some(if redelivery {
+                            FireOutcome::Redelivered
+                        } else {
+                            FireOutcome::Fired
+                        }))
+                    })
+                    .unwrap_or_else(|e: spi::Error| error!("{}", e))
+                });
+
+                let message = match fired {
+                    Ok(Some(outcome)) => {
+                        if let Some(stats) = stats {
+                            match outcome {
+                                FireOutcome::Missed => stats.missed.incr(),
+                                FireOutcome::Redelivered => stats.fired.incr(),
+                                FireOutcome::Fired => {
+                                    stats.fired.incr();
+                                    stats.record_fire_latency(Local::now() - row.expires_at);
+                                }
+                            }
+                        }
+
+                        config::log_activity!(
+                            "quartz-worker-{}: timer {} {}",
+                            worker_id,
+                            TimerId::new(table_oid, row.id),
+                            outcome
+                        );
+
+                        return Ok(());
+                    }
+                    Ok(None) => {
+                        warning!(
+                            "quartz-worker-{}: timer {} is no longer pending",
+                            worker_id,
+                            TimerId::new(table_oid, row.id)
+                        );
+
+                        return Ok(());
+                    }
+                    Err(message) => {
+                        if let Some(stats) = stats {
+                            stats.failed.incr();
+                        }
+
+                        message
+                    }
+                };
+
+                // The next attempt is stored as the new expiration of the
+                // timer, in the same transaction as the failure, so that it
+                // survives a restart. The timer subsystem arms it again once
+                // the update commits, like any rescheduled timer.
+                let failure = Spi::connect(|mut client| -> Result<_, spi::Error> {
+                    let attempts = match commands::record_timer_failure(
+                        &mut client,
+                        schema.as_str(),
+                        table.as_str(),
+                        &columns,
+                        row.id,
+                        message.as_str(),
+                    )? {
+                        Some(value) => value,
+                        None => return Ok(None),
+                    };
+
+                    // Redeliveries are retried at the next start instead.
+                    if attempts >= retry_policy.max_attempts || redelivery {
+                        return Ok(Some((attempts, None)));
+                    }
+
+                    let delay = retry_policy
+                        .delay(attempts)
+                        .max(chrono::Duration::from_std(RETRY_MIN_DELAY).unwrap());
+
+                    commands::retry_timer_at(
+                        &mut client,
+                        schema.as_str(),
+                        table.as_str(),
+                        &columns,
+                        row.id,
+                        Local::now() + delay,
+                    )?;
+
+                    Ok(Some((attempts, Some(delay))))
+                })?;
+
+                let (attempts, delay) = match failure {
+                    Some(value) => value,
+                    None => return Ok(()),
+                };
+
+                match delay {
+                    Some(delay) => warning!(
+                        "quartz-worker-{}: timer {} failed (attempt {} of {}), retrying in {}: {}",
+                        worker_id,
+                        TimerId::new(table_oid, row.id),
+                        attempts,
+                        retry_policy.max_attempts,
+                        delay,
+                        message
+                    ),
+                    None if attempts >= retry_policy.max_attempts => warning!(
+                        "quartz-worker-{}: timer {} failed (attempt {} of {}), giving up: {}",
+                        worker_id,
+                        TimerId::new(table_oid, row.id),
+                        attempts,
+                        retry_policy.max_attempts,
+                        message
+                    ),
+                    None => warning!(
+                        "quartz-worker-{}: redelivery of timer {} failed (attempt {} of {}), redelivering at the next start: {}",
+                        worker_id,
+                        TimerId::new(table_oid, row.id),
+                        attempts,
+                        retry_policy.max_attempts,
+                        message
+                    ),
+                }
 
                 Ok(())
-            })
-        });
+            });
+
+        // If the transaction failed, the worker exits below and the timer
+        // stays in flight, to be recovered once the worker is restarted.
+        if result.is_ok() {
+            self.in_flight.clear();
+            self.handle.finish_firing(table_oid, row.id);
+        }
 
         if let Err(e) = result {
             error!(
                 "quartz-worker-{}: process timer {} fired: {}",
-                self.worker_id, row.id, e
+                worker_id,
+                TimerId::new(table_oid, row.id),
+                e
             );
         }
     }